@@ -0,0 +1,72 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut string = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                string.push(ALPHABET[(value >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                string.push('=');
+            }
+        }
+    }
+    string
+}
+
+/// Returns `None` if the data is not valid base64. Padding is optional.
+pub fn decode(string: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(string.len() / 4 * 3);
+    let mut value = 0u32;
+    let mut bits = 0;
+    for &c in string.iter().take_while(|&&c| c != b'=') {
+        let digit = ALPHABET.iter().position(|&a| a == c)? as u32;
+        value = value << 6 | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((value >> bits) as u8);
+            value &= (1 << bits) - 1;
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known() {
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(b"f"), "Zg==");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"foo"), "Zm9v");
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(decode(b"Zm9vYg=="), Some(b"foob".to_vec()));
+        assert_eq!(decode(b"Zm9vYg"), Some(b"foob".to_vec()));
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..data.len() {
+            assert_eq!(
+                decode(encode(&data[..len]).as_bytes()),
+                Some(data[..len].to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode(b"Zm9v!"), None);
+        assert_eq!(decode(b"Zm 9v"), None);
+    }
+}
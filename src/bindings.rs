@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use config::{Config, KeyBinding};
+use keyboard::Modifiers;
+use orbclient;
+
+/// Built-in actions that can be bound to a key chord
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Copy,
+    /// Copy the selection as HTML, keeping its colors
+    CopyHtml,
+    /// Copy the selection as text with SGR sequences, keeping its colors
+    CopyAnsi,
+    Paste,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    /// Scroll by a line. There is no scrollback, so this scrolls applications that track the
+    /// mouse or use the alternate screen
+    ScrollUp,
+    ScrollDown,
+    Reset,
+    /// Select text with vi style keys
+    CopyMode,
+    /// Search with copy mode
+    Search,
+    /// Label matches of the hint rules, to act on them by typing their label
+    Hints,
+    /// Move to a prompt marked with OSC 133 in copy mode
+    PreviousPrompt,
+    NextPrompt,
+    /// Select the part of the output of the last command that is on the screen, as marked with
+    /// OSC 133
+    SelectOutput,
+    /// Copy the whole output of the last command, including rows that scrolled off the screen
+    CopyOutput,
+    /// Open another terminal in the working directory reported by the shell
+    NewWindow,
+}
+
+impl Action {
+    fn from_name(name: &str) -> Option<Action> {
+        Some(match name {
+            "copy" => Action::Copy,
+            "copy_html" => Action::CopyHtml,
+            "copy_ansi" => Action::CopyAnsi,
+            "paste" => Action::Paste,
+            "zoom_in" => Action::ZoomIn,
+            "zoom_out" => Action::ZoomOut,
+            "zoom_reset" => Action::ZoomReset,
+            "scroll_up" => Action::ScrollUp,
+            "scroll_down" => Action::ScrollDown,
+            "reset" => Action::Reset,
+            "copy_mode" => Action::CopyMode,
+            "search" => Action::Search,
+            "hints" => Action::Hints,
+            "previous_prompt" => Action::PreviousPrompt,
+            "next_prompt" => Action::NextPrompt,
+            "select_output" => Action::SelectOutput,
+            "copy_output" => Action::CopyOutput,
+            "new_window" => Action::NewWindow,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Binding {
+    Action(Action),
+    /// Bytes sent to the PTY as if they were typed
+    Send(Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chord {
+    pub modifiers: Modifiers,
+    pub scancode: u8,
+}
+
+impl Chord {
+    /// Parse a chord like `ctrl+shift+c`
+    fn parse(string: &str) -> Result<Chord, String> {
+        let mut modifiers = Modifiers {
+            shift: false,
+            alt: false,
+            ctrl: false,
+        };
+        let mut scancode = None;
+
+        for part in string.split('+') {
+            let part = part.trim().to_lowercase();
+            match part.as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" => modifiers.alt = true,
+                _ if scancode.is_some() => {
+                    return Err(format!("more than one key in {:?}", string))
+                }
+                name => match key(name) {
+                    Some(key) => scancode = Some(key),
+                    None => return Err(format!("unknown key {:?} in {:?}", name, string)),
+                },
+            }
+        }
+
+        match scancode {
+            Some(scancode) => Ok(Chord {
+                modifiers,
+                scancode,
+            }),
+            None => Err(format!("no key in {:?}", string)),
+        }
+    }
+}
+
+/// Scancode of a key name
+fn key(name: &str) -> Option<u8> {
+    Some(match name {
+        "a" => orbclient::K_A,
+        "b" => orbclient::K_B,
+        "c" => orbclient::K_C,
+        "d" => orbclient::K_D,
+        "e" => orbclient::K_E,
+        "f" => orbclient::K_F,
+        "g" => orbclient::K_G,
+        "h" => orbclient::K_H,
+        "i" => orbclient::K_I,
+        "j" => orbclient::K_J,
+        "k" => orbclient::K_K,
+        "l" => orbclient::K_L,
+        "m" => orbclient::K_M,
+        "n" => orbclient::K_N,
+        "o" => orbclient::K_O,
+        "p" => orbclient::K_P,
+        "q" => orbclient::K_Q,
+        "r" => orbclient::K_R,
+        "s" => orbclient::K_S,
+        "t" => orbclient::K_T,
+        "u" => orbclient::K_U,
+        "v" => orbclient::K_V,
+        "w" => orbclient::K_W,
+        "x" => orbclient::K_X,
+        "y" => orbclient::K_Y,
+        "z" => orbclient::K_Z,
+        "0" => orbclient::K_0,
+        "1" => orbclient::K_1,
+        "2" => orbclient::K_2,
+        "3" => orbclient::K_3,
+        "4" => orbclient::K_4,
+        "5" => orbclient::K_5,
+        "6" => orbclient::K_6,
+        "7" => orbclient::K_7,
+        "8" => orbclient::K_8,
+        "9" => orbclient::K_9,
+        "grave" | "`" => orbclient::K_TICK,
+        "minus" | "-" => orbclient::K_MINUS,
+        "equals" | "=" => orbclient::K_EQUALS,
+        "bracketleft" | "[" => orbclient::K_BRACE_OPEN,
+        "bracketright" | "]" => orbclient::K_BRACE_CLOSE,
+        "backslash" | "\\" => orbclient::K_BACKSLASH,
+        "semicolon" | ";" => orbclient::K_SEMICOLON,
+        "quote" | "'" => orbclient::K_QUOTE,
+        "comma" | "," => orbclient::K_COMMA,
+        "period" | "." => orbclient::K_PERIOD,
+        "slash" | "/" => orbclient::K_SLASH,
+        "space" => orbclient::K_SPACE,
+        "tab" => orbclient::K_TAB,
+        "enter" | "return" => orbclient::K_ENTER,
+        "backspace" => orbclient::K_BKSP,
+        "escape" | "esc" => orbclient::K_ESC,
+        "insert" => orbclient::K_INS,
+        "delete" => orbclient::K_DEL,
+        "home" => orbclient::K_HOME,
+        "end" => orbclient::K_END,
+        "pageup" => orbclient::K_PGUP,
+        "pagedown" => orbclient::K_PGDN,
+        "up" => orbclient::K_UP,
+        "down" => orbclient::K_DOWN,
+        "left" => orbclient::K_LEFT,
+        "right" => orbclient::K_RIGHT,
+        "f1" => orbclient::K_F1,
+        "f2" => orbclient::K_F2,
+        "f3" => orbclient::K_F3,
+        "f4" => orbclient::K_F4,
+        "f5" => orbclient::K_F5,
+        "f6" => orbclient::K_F6,
+        "f7" => orbclient::K_F7,
+        "f8" => orbclient::K_F8,
+        "f9" => orbclient::K_F9,
+        "f10" => orbclient::K_F10,
+        "f11" => orbclient::K_F11,
+        "f12" => orbclient::K_F12,
+        _ => return None,
+    })
+}
+
+pub struct Bindings {
+    bindings: Vec<(Chord, Binding)>,
+}
+
+impl Bindings {
+    /// Merge the `[keybindings]` table of the config over the default bindings. Invalid and
+    /// conflicting entries are skipped and returned as errors.
+    pub fn new(table: &BTreeMap<String, KeyBinding>) -> (Bindings, Vec<String>) {
+        let (mut bindings, _errors) = Bindings::parse(&Config::default().keybindings);
+        let (user_bindings, errors) = Bindings::parse(table);
+        for (chord, binding) in user_bindings {
+            bindings.retain(|(other, _)| *other != chord);
+            bindings.push((chord, binding));
+        }
+
+        let bindings = bindings
+            .into_iter()
+            .filter_map(|(chord, binding)| binding.map(|binding| (chord, binding)))
+            .collect();
+        (Bindings { bindings }, errors)
+    }
+
+    /// Parse a table of key bindings, chords bound to `none` have no binding
+    fn parse(table: &BTreeMap<String, KeyBinding>) -> (Vec<(Chord, Option<Binding>)>, Vec<String>) {
+        let mut bindings: Vec<(Chord, Option<Binding>)> = Vec::new();
+        let mut names: Vec<&str> = Vec::new();
+        let mut errors = Vec::new();
+
+        for (name, key_binding) in table.iter() {
+            let chord = match Chord::parse(name) {
+                Ok(chord) => chord,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+
+            let binding = match *key_binding {
+                KeyBinding::Action(ref action) if action == "none" => None,
+                KeyBinding::Action(ref action) => match Action::from_name(action) {
+                    Some(action) => Some(Binding::Action(action)),
+                    None => {
+                        errors.push(format!("unknown action {:?} for {:?}", action, name));
+                        continue;
+                    }
+                },
+                KeyBinding::Send { ref send } => Some(Binding::Send(send.clone().into_bytes())),
+            };
+
+            if let Some(i) = bindings.iter().position(|(other, _)| *other == chord) {
+                errors.push(format!("{:?} conflicts with {:?}", name, names[i]));
+                continue;
+            }
+
+            bindings.push((chord, binding));
+            names.push(name);
+        }
+
+        (bindings, errors)
+    }
+
+    pub fn get(&self, scancode: u8, modifiers: Modifiers) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|(chord, _)| chord.scancode == scancode && chord.modifiers == modifiers)
+            .map(|(_, binding)| binding)
+    }
+}
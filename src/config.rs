@@ -1,20 +1,289 @@
 use failure::Error;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
 use toml;
 use xdg::BaseDirectories;
 
-#[derive(Serialize, Deserialize)]
+/// What the backspace key sends, applications can switch this with DECBKM
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backspace {
+    /// DEL, `^?`
+    Del,
+    /// BS, `^H`
+    Bs,
+}
+
+/// What the delete key sends
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delete {
+    /// The VT220 sequence, `ESC [ 3 ~`
+    Vt220,
+    /// DEL, `^?`
+    Del,
+}
+
+/// Parse a `#rrggbb` color
+pub fn parse_color(string: &str) -> Option<u32> {
+    if string.len() != 7
+        || !string.starts_with('#')
+        || !string[1..].chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    u32::from_str_radix(&string[1..], 16).ok()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasteConfig {
+    /// Ask before pasting text with line breaks or control characters
+    pub confirm: bool,
+    /// Also ask when the application has enabled bracketed paste
+    pub confirm_bracketed: bool,
+}
+impl Default for PasteConfig {
+    fn default() -> Self {
+        PasteConfig {
+            confirm: true,
+            confirm_bracketed: false,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectionConfig {
+    /// Characters that end a word when double clicking, in addition to whitespace
+    pub word_separators: String,
+    /// Copy the selection to the clipboard when the mouse button is released
+    pub copy_on_select: bool,
+    /// Colors of selected text, as `#rrggbb`
+    pub foreground: String,
+    pub background: String,
+}
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        SelectionConfig {
+            word_separators: ",│`|:\"'()[]{}<>".to_string(),
+            copy_on_select: false,
+            foreground: "#000000".to_string(),
+            background: "#c0c0c0".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// Let applications set the clipboard with OSC 52
+    pub allow_write: bool,
+    /// Let applications read the clipboard with OSC 52
+    pub allow_read: bool,
+}
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        ClipboardConfig {
+            allow_write: true,
+            allow_read: false,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinksConfig {
+    /// Command that opens a link on Ctrl+click, the link is passed as the last argument
+    pub opener: String,
+}
+impl Default for LinksConfig {
+    fn default() -> Self {
+        LinksConfig {
+            opener: if cfg!(target_os = "redox") {
+                "launcher".to_string()
+            } else {
+                "xdg-open".to_string()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TitleConfig {
+    /// Format of the window title. `{title}` and `{icon}` are replaced by the title and icon name
+    /// set by the application, `{cwd}` by the working directory reported by the shell with OSC 7
+    /// and `{process}` by the name of the program run in the terminal.
+    pub template: String,
+    /// Let applications read the title with CSI 20 t and CSI 21 t
+    pub allow_report: bool,
+    /// Deprecated, `true` is read as a template of `{title} - {cwd}`
+    #[serde(skip_serializing)]
+    pub show_cwd: bool,
+}
+impl Default for TitleConfig {
+    fn default() -> Self {
+        TitleConfig {
+            template: "{title}".to_string(),
+            allow_report: false,
+            show_cwd: false,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    /// Show notifications sent with OSC 9 and OSC 777 in the window
+    pub show: bool,
+    /// Command run for each notification, the title and body are passed as the last arguments
+    pub command: String,
+}
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            show: true,
+            command: String::new(),
+        }
+    }
+}
+
+/// What happens when a hint is chosen
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HintAction {
+    /// Copy the text to the clipboard
+    Copy,
+    /// Open the text with the link opener
+    Open,
+    /// Send the text to the PTY as if it was typed
+    Send,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HintRule {
+    pub regex: String,
+    pub action: HintAction,
+    /// Text given to the action, `{0}` is replaced by the match and `{1}` or `{name}` by its groups
+    #[serde(default = "HintRule::default_template")]
+    pub template: String,
+}
+impl HintRule {
+    fn default_template() -> String {
+        "{0}".to_string()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HintsConfig {
+    /// Characters used for the labels of hints
+    pub alphabet: String,
+    pub rules: Vec<HintRule>,
+}
+impl Default for HintsConfig {
+    fn default() -> Self {
+        let rule = |regex: &str, action: HintAction, template: &str| HintRule {
+            regex: regex.to_string(),
+            action,
+            template: template.to_string(),
+        };
+
+        HintsConfig {
+            alphabet: "asdfghjkl".to_string(),
+            rules: vec![
+                rule(
+                    r#"(?:https?|ftp|file)://[^\s<>"'`{}|\\^]+[^\s<>"'`{}|\\^.,:;!?)\]]"#,
+                    HintAction::Open,
+                    "{0}",
+                ),
+                rule(
+                    r"(?P<file>[\w./~-]+\.\w+):(?P<line>\d+)(?::\d+)?",
+                    HintAction::Copy,
+                    "{file}:{line}",
+                ),
+                // A hash has at least one letter, so plain numbers are left out
+                rule(
+                    r"\b(?:[a-f][0-9a-f]{6,}|[0-9][a-f][0-9a-f]{5,}|[0-9]{2}[a-f][0-9a-f]{4,}|[0-9]{3}[a-f][0-9a-f]{3,}|[0-9]{4}[a-f][0-9a-f]{2,}|[0-9]{5}[a-f][0-9a-f]+|[0-9]{6,}[a-f][0-9a-f]*)\b",
+                    HintAction::Copy,
+                    "{0}",
+                ),
+                rule(r"\b\d{1,3}(?:\.\d{1,3}){3}\b", HintAction::Copy, "{0}"),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyBinding {
+    /// Name of a built-in action, like `copy` or `zoom_in`, or `none` to remove a default binding
+    Action(String),
+    /// Text sent to the PTY as if it was typed
+    Send { send: String },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub font: String,
     pub font_bold: String,
+    pub backspace: Backspace,
+    pub delete: Delete,
+    pub paste: PasteConfig,
+    pub selection: SelectionConfig,
+    pub clipboard: ClipboardConfig,
+    pub links: LinksConfig,
+    pub hints: HintsConfig,
+    pub title: TitleConfig,
+    pub notifications: NotificationsConfig,
+    pub keybindings: BTreeMap<String, KeyBinding>,
 }
 impl Default for Config {
     fn default() -> Self {
+        let mut keybindings = BTreeMap::new();
+        for &(chord, action) in [
+            ("ctrl+shift+c", "copy"),
+            ("ctrl+shift+v", "paste"),
+            ("ctrl+equals", "zoom_in"),
+            ("ctrl+minus", "zoom_out"),
+            ("ctrl+0", "zoom_reset"),
+            // Zooming works with or without shift, so ctrl++ zooms in on layouts with + above =
+            ("ctrl+shift+equals", "zoom_in"),
+            ("ctrl+shift+minus", "zoom_out"),
+            ("ctrl+shift+0", "zoom_reset"),
+            ("ctrl+shift+up", "scroll_up"),
+            ("ctrl+shift+down", "scroll_down"),
+            ("ctrl+shift+f", "search"),
+            ("ctrl+shift+space", "copy_mode"),
+            ("ctrl+shift+e", "hints"),
+            ("ctrl+shift+n", "new_window"),
+            ("ctrl+shift+z", "previous_prompt"),
+            ("ctrl+shift+x", "next_prompt"),
+            ("ctrl+shift+g", "select_output"),
+            ("ctrl+shift+o", "copy_output"),
+        ]
+        .iter()
+        {
+            keybindings.insert(chord.to_string(), KeyBinding::Action(action.to_string()));
+        }
+
         Config {
             font: String::new(),
             font_bold: String::new(),
+            backspace: Backspace::Del,
+            delete: Delete::Vt220,
+            paste: PasteConfig::default(),
+            selection: SelectionConfig::default(),
+            clipboard: ClipboardConfig::default(),
+            links: LinksConfig::default(),
+            hints: HintsConfig::default(),
+            title: TitleConfig::default(),
+            notifications: NotificationsConfig::default(),
+            keybindings,
         }
     }
 }
@@ -35,7 +304,12 @@ impl Config {
         let mut file = File::open(path)?;
         let mut contents = Vec::new();
         file.read_to_end(&mut contents)?;
-        toml::from_slice(&contents).map_err(Error::from)
+        let mut config: Config = toml::from_slice(&contents)?;
+        // show_cwd was replaced by the title template
+        if config.title.show_cwd && config.title.template == TitleConfig::default().template {
+            config.title.template = "{title} - {cwd}".to_string();
+        }
+        Ok(config)
     }
 
     pub fn write<P: AsRef<Path>>(&self, path: &P) -> Result<(), Error> {
@@ -1,23 +1,167 @@
 extern crate ransid;
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
 use std::io::Result;
-use std::{cmp, mem, ptr};
-
-use config::Config;
+use std::ops::Range;
+use std::os::unix::io::RawFd;
+use std::process;
+use std::time::{Duration, Instant};
+use std::{cmp, env, mem, ptr, thread};
+
+use base64;
+use bindings::{Action, Binding, Bindings};
+use config::{self, Backspace, Config, Delete, HintAction};
+use copy_mode::{Command, CopyMode, Screen};
+use export;
+use hints::{self, Hint, Hints, Rules};
+use keyboard;
 use orbclient::{Color, EventOption, MediaKind, Mode, Renderer, Window, WindowFlag};
 use orbfont::Font;
+use palette::{self, Palette, Target};
+use parser::{self, Parser, Progress};
+use paste::PendingPaste;
+use selection::{Point, Selection};
+use url;
+
+/// Size of the link table before unused links are removed
+const MAX_LINKS: usize = 1024;
+
+/// Longest time between the clicks of a double or triple click
+const MULTI_CLICK: Duration = Duration::from_millis(500);
+
+/// How long a notification is shown in the window
+const TOAST_TIME: Duration = Duration::from_secs(5);
+
+/// Shortest time between runs of the notification command
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum depth of the title stack, older entries are dropped
+const TITLE_STACK: usize = 10;
+
+/// Rows of command output kept after scrolling off the screen, older rows are dropped
+const MAX_OUTPUT_ROWS: usize = 1000;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Block {
-    c: char,
-    fg: Color,
-    bg: Color,
-    bold: bool,
+    pub c: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    /// Colors as set by the application, to resolve them again when the palette changes
+    pub raw_fg: ransid::Color,
+    pub raw_bg: ransid::Color,
+    /// Set on the last block of a row when the text continues on the next row
+    pub wrapped: bool,
+    /// Hyperlink of the block, an index into the link table plus one, or zero
+    pub link: usize,
+    /// Shell integration mark at the block
+    pub mark: Option<Mark>,
+}
+
+/// A point in the shell's command cycle, marked with OSC 133
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mark {
+    /// Start of a prompt, with the exit status of the command entered at it once it finished
+    Prompt { exit: Option<i32> },
+    /// Start of the command line
+    Command,
+    /// Start of the output of the command
+    Output,
+    /// End of the output of the command
+    End,
+}
+
+/// Whether the host of an OSC 7 URL is this machine
+fn is_local_host(host: &str) -> bool {
+    if host.is_empty() || host == "localhost" {
+        return true;
+    }
+    let mut name = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len() - 1) };
+    result == 0 && name.split(|&b| b == 0).next() == Some(host.as_bytes())
+}
+
+/// Keep the part of a row scrolling off the screen that belongs to the last command output
+fn keep_output(row: &[Block], rows: &mut VecDeque<(Vec<Block>, bool)>, open: &mut bool) {
+    let mut kept = Vec::new();
+    for block in row {
+        match block.mark {
+            Some(Mark::Output) => {
+                rows.clear();
+                kept.clear();
+                *open = true;
+            }
+            Some(Mark::End) | Some(Mark::Prompt { .. }) => *open = false,
+            _ => (),
+        }
+        if *open {
+            kept.push(*block);
+        }
+    }
+    if kept.is_empty() {
+        return;
+    }
+
+    let wrapped = *open
+        && match row.last() {
+            Some(block) => block.wrapped,
+            None => false,
+        };
+    if !wrapped {
+        let len = kept
+            .iter()
+            .rposition(|block| block.c != '\0' && block.c != ' ')
+            .map_or(0, |i| i + 1);
+        kept.truncate(len);
+    }
+    rows.push_back((kept, wrapped));
+    if rows.len() > MAX_OUTPUT_ROWS {
+        rows.pop_front();
+    }
+}
+
+/// Replace the `{name}` placeholders of a title template, unknown ones are kept
+fn template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut title = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        title.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(name, _)| *name == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                title.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                title.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    title.push_str(rest);
+    title
+}
+
+/// A hyperlink set with OSC 8
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub uri: String,
+    pub id: Option<String>,
 }
 
 pub struct Console {
+    pub config: Config,
+    pub bindings: Bindings,
     pub ransid: ransid::Console,
+    pub parser: Parser,
     pub window: Window,
     pub alternate: bool,
     pub grid: Box<[Block]>,
@@ -28,15 +172,71 @@ pub struct Console {
     pub mouse_x: u16,
     pub mouse_y: u16,
     pub mouse_left: bool,
+    pub mouse_middle: bool,
     pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub skip_text: bool,
     pub input: Vec<u8>,
     pub block_width: usize,
     pub block_height: usize,
     pub default_block_width: usize,
     pub default_block_height: usize,
     pub alpha: u8,
-    pub selection: Option<(usize, usize)>,
-    pub last_selection: Option<(usize, usize)>,
+    pub palette: Palette,
+    /// Window title and icon name set by the application
+    pub title: String,
+    pub icon: String,
+    /// Titles and icon names saved with CSI 22 t
+    pub title_stack: Vec<(Option<String>, Option<String>)>,
+    /// Name of the program run in the terminal, shown when the foreground process is unknown
+    pub process: String,
+    /// Master side of the PTY, to look up its foreground process group
+    pub master_fd: RawFd,
+    /// Foreground process group of the PTY when the title was last updated
+    pub foreground: libc::pid_t,
+    /// Working directory reported by the shell with OSC 7, if it is on this machine
+    pub cwd: Option<String>,
+    pub focused: bool,
+    /// A notification arrived while the window was not focused
+    pub attention: bool,
+    /// Lines of the notification shown in the window, and when it is hidden
+    pub toast: Option<(Vec<String>, Instant)>,
+    /// Progress of a task reported with OSC 9;4
+    pub progress: Option<Progress>,
+    /// Run of the notification command and when it started, kept while it runs and for
+    /// `NOTIFY_INTERVAL` after it started
+    pub notifier: Option<(process::Child, Instant)>,
+    pub selection: Option<Selection>,
+    pub last_selection: Option<Selection>,
+    /// Text of the most recent mouse selection, pasted with a middle click
+    pub primary: String,
+    /// Hyperlinks of blocks, unused entries are removed once the table is full
+    pub links: Vec<Option<Link>>,
+    /// Hyperlink of printed text
+    pub link: usize,
+    /// Link underlined because the mouse is over it
+    pub hover: Option<Range<usize>>,
+    pub selection_fg: Color,
+    pub selection_bg: Color,
+    /// Clicks in the current run of double or triple clicks
+    pub click_count: usize,
+    pub last_click: Option<(Instant, usize)>,
+    /// Word or line selected by a double or triple click, kept while dragging
+    pub selection_anchor: (Point, Point),
+    /// Lines scrolled off the top of the screen, which selections are anchored to
+    pub scrolled: isize,
+    pub alt_scrolled: isize,
+    /// Position of the last printed character, to detect text wrapping onto the next row
+    pub last_char: Option<(usize, usize)>,
+    /// Rows of the last command output that scrolled off the screen, like `selection_rows`
+    pub scrolled_output: VecDeque<(Vec<Block>, bool)>,
+    /// The last command output continues below the rows in `scrolled_output`
+    pub output_open: bool,
+    pub pending_paste: Option<PendingPaste>,
+    pub copy_mode: Option<CopyMode>,
+    pub hint_rules: Rules,
+    pub hints: Option<Hints>,
 }
 
 impl Console {
@@ -46,11 +246,14 @@ impl Console {
         height: u32,
         block_width: usize,
         block_height: usize,
+        master_fd: RawFd,
+        process: &str,
     ) -> Console {
         let alpha = 224;
-        let cvt = |color: ransid::Color| -> Color {
+        let palette = Palette::new();
+        let cvt = |rgb: u32| -> Color {
             Color {
-                data: ((alpha as u32) << 24) | (color.as_rgb() & 0xFFFFFF),
+                data: ((alpha as u32) << 24) | rgb,
             }
         };
 
@@ -70,15 +273,20 @@ impl Console {
             ],
         )
         .unwrap();
-        window.set(cvt(ransid.state.background));
+        window.set(cvt(palette.bg(ransid.state.background)));
         window.sync();
 
         let grid = vec![
             Block {
                 c: '\0',
-                fg: cvt(ransid.state.foreground),
-                bg: cvt(ransid.state.background),
-                bold: false
+                fg: cvt(palette.fg(ransid.state.foreground)),
+                bg: cvt(palette.bg(ransid.state.background)),
+                bold: false,
+                raw_fg: ransid.state.foreground,
+                raw_bg: ransid.state.background,
+                wrapped: false,
+                link: 0,
+                mark: None
             };
             ransid.state.w * ransid.state.h
         ]
@@ -94,8 +302,37 @@ impl Console {
             Font::find(Some("Mono"), None, Some("Bold")).expect("Cannot find a bold monospace font")
         });
 
-        Console {
+        let (bindings, errors) = Bindings::new(&config.keybindings);
+        for error in errors {
+            eprintln!("orbterm: ignoring key binding: {}", error);
+        }
+
+        let selection_color = |string: &str, default: u32| -> Color {
+            let rgb = config::parse_color(string).unwrap_or_else(|| {
+                eprintln!("orbterm: invalid selection color {:?}", string);
+                default
+            });
+            Color {
+                data: ((alpha as u32) << 24) | rgb,
+            }
+        };
+        let selection_fg = selection_color(&config.selection.foreground, 0x000000);
+        let selection_bg = selection_color(&config.selection.background, 0xC0C0C0);
+
+        let (hint_rules, errors) = Rules::new(&config.hints.rules);
+        for error in errors {
+            eprintln!("orbterm: ignoring hint rule: {}", error);
+        }
+
+        let mut parser = Parser::new();
+        parser.state.backarrow = config.backspace == Backspace::Bs;
+        parser.state.backarrow_default = parser.state.backarrow;
+
+        let mut console = Console {
+            config: config.clone(),
+            bindings,
             ransid,
+            parser,
             window,
             alternate: false,
             grid,
@@ -106,68 +343,771 @@ impl Console {
             mouse_x: 0,
             mouse_y: 0,
             mouse_left: false,
+            mouse_middle: false,
             ctrl: false,
+            shift: false,
+            alt: false,
+            skip_text: false,
             input: Vec::new(),
             block_width,
             block_height,
             default_block_width: block_width,
             default_block_height: block_height,
             alpha,
+            palette,
+            title: "Terminal".to_string(),
+            icon: "Terminal".to_string(),
+            title_stack: Vec::new(),
+            process: process.to_string(),
+            master_fd,
+            foreground: 0,
+            cwd: None,
+            focused: true,
+            attention: false,
+            toast: None,
+            progress: None,
+            notifier: None,
             selection: None,
             last_selection: None,
+            primary: String::new(),
+            links: Vec::new(),
+            link: 0,
+            hover: None,
+            selection_fg,
+            selection_bg,
+            click_count: 0,
+            last_click: None,
+            selection_anchor: (Point { line: 0, column: 0 }, Point { line: 0, column: 0 }),
+            scrolled: 0,
+            alt_scrolled: 0,
+            last_char: None,
+            scrolled_output: VecDeque::new(),
+            output_open: false,
+            pending_paste: None,
+            copy_mode: None,
+            hint_rules,
+            hints: None,
+        };
+        console.update_title();
+        console
+    }
+
+    /// Blocks of the selection by row, each with a flag that is set when the row continues on the
+    /// next one. Trailing blanks are removed from rows that do not continue.
+    pub fn selection_rows(&self) -> Vec<(Vec<Block>, bool)> {
+        match self.selection {
+            Some(ref selection) => self.rows(selection),
+            None => Vec::new(),
         }
     }
 
+    /// Blocks of any selection by row, like `selection_rows`
+    fn rows(&self, selection: &Selection) -> Vec<(Vec<Block>, bool)> {
+        let mut rows = Vec::new();
+        let w = self.ransid.state.w;
+        for range in self.selection_ranges(selection) {
+            let row_end = (range.start / w + 1) * w;
+            let wrapped =
+                !selection.block && range.end == row_end && self.grid[row_end - 1].wrapped;
+
+            let mut row = self.grid[range].to_vec();
+            if !wrapped {
+                let len = row
+                    .iter()
+                    .rposition(|block| block.c != '\0' && block.c != ' ')
+                    .map_or(0, |i| i + 1);
+                row.truncate(len);
+            }
+            rows.push((row, wrapped));
+        }
+        rows
+    }
+
+    /// Selection of the part of the last command output on the screen, as marked with OSC 133,
+    /// and whether the output started above the screen
+    fn last_output(&self) -> Option<(Selection, bool)> {
+        let cursor = self.cursor_index();
+        let start = self.grid[..cursor]
+            .iter()
+            .rposition(|block| block.mark == Some(Mark::Output));
+        // Output that started above the screen continues in its first row
+        let (start, scrolled) = match start {
+            Some(start) => (start, false),
+            None if self.output_open => (0, true),
+            None => return None,
+        };
+        // The output ends where the next prompt starts, or at the cursor while it runs
+        let end = self.grid[start..cursor]
+            .iter()
+            .position(|block| matches!(block.mark, Some(Mark::End) | Some(Mark::Prompt { .. })))
+            .map_or(cursor, |i| start + i);
+        Some((Selection::new(self.point(start), self.point(end)), scrolled))
+    }
+
     pub fn selection_text(&self) -> String {
-        let mut string = String::new();
-        if let Some(selection) = self.selection {
-            let mut skipping = false;
-            for i in cmp::min(selection.0, selection.1)..cmp::max(selection.0, selection.1) {
-                if let Some(block) = self.grid.get(i) {
-                    if block.c == '\0' {
-                        skipping = true;
-                    } else {
-                        if skipping {
-                            string.push('\n');
-                            skipping = false;
-                        }
-                        string.push(block.c);
+        export::plain(&self.selection_rows())
+    }
+
+    /// Whether a row continues the row above it
+    fn wrapped(&self, y: usize) -> bool {
+        if y == 0 || y >= self.ransid.state.h {
+            return false;
+        }
+        match self.grid.get(y * self.ransid.state.w - 1) {
+            Some(block) => block.wrapped,
+            None => false,
+        }
+    }
+
+    /// Whether a grid index is on the same line as the index before it
+    fn joined(&self, i: usize) -> bool {
+        let x = i % self.ransid.state.w;
+        let y = i / self.ransid.state.w;
+        x > 0 || self.wrapped(y)
+    }
+
+    /// Range of the word at a grid index
+    fn word_at(&self, i: usize) -> (usize, usize) {
+        let is_word = |i: usize| match self.grid.get(i) {
+            Some(block) => {
+                block.c != '\0'
+                    && !block.c.is_whitespace()
+                    && !self.config.selection.word_separators.contains(block.c)
+            }
+            None => false,
+        };
+
+        if !is_word(i) {
+            return (i, i + 1);
+        }
+
+        let mut start = i;
+        while start > 0 && is_word(start - 1) && self.joined(start) {
+            start -= 1;
+        }
+        let mut end = i + 1;
+        while is_word(end) && self.joined(end) {
+            end += 1;
+        }
+        (start, end)
+    }
+
+    /// Range of the line at a grid index, including the rows it wrapped onto
+    fn line_at(&self, i: usize) -> (usize, usize) {
+        let w = self.ransid.state.w;
+        let mut start = cmp::min(i / w, self.ransid.state.h - 1);
+        while self.wrapped(start) {
+            start -= 1;
+        }
+        let mut end = start + 1;
+        while self.wrapped(end) {
+            end += 1;
+        }
+        (start * w, end * w)
+    }
+
+    /// Range selected at a grid index for the current click count
+    fn select_unit(&self, i: usize) -> (Point, Point) {
+        let (start, end) = match self.click_count {
+            2 => self.word_at(i),
+            3 => self.line_at(i),
+            _ => (i, i),
+        };
+        (self.point(start), self.point(end))
+    }
+
+    /// Position of a grid index that stays with the text when it scrolls
+    fn point(&self, i: usize) -> Point {
+        let w = self.ransid.state.w;
+        Point {
+            line: self.scrolled + (i / w) as isize,
+            column: i % w,
+        }
+    }
+
+    /// Grid indices of the visible part of a selection, one range per row
+    fn selection_ranges(&self, selection: &Selection) -> Vec<Range<usize>> {
+        selection.ranges(self.scrolled, self.ransid.state.w, self.ransid.state.h)
+    }
+
+    /// Link at a grid index, along with the grid indices it covers
+    fn link_at(&self, i: usize) -> Option<(Range<usize>, String)> {
+        let link = match self.grid.get(i) {
+            Some(block) => block.link,
+            None => return None,
+        };
+
+        if link != 0 {
+            if let Some(Some(ref hyperlink)) = self.links.get(link - 1) {
+                let mut start = i;
+                while start > 0 && self.grid[start - 1].link == link {
+                    start -= 1;
+                }
+                let mut end = i + 1;
+                while end < self.grid.len() && self.grid[end].link == link {
+                    end += 1;
+                }
+                return Some((start..end, hyperlink.uri.clone()));
+            }
+        }
+
+        let (start, end) = self.line_at(i);
+        let chars: Vec<char> = self.grid[start..end].iter().map(|block| block.c).collect();
+        url::find(&chars)
+            .into_iter()
+            .find(|range| start + range.start <= i && i < start + range.end)
+            .map(|range| {
+                let link = chars[range.clone()].iter().collect();
+                (start + range.start..start + range.end, link)
+            })
+    }
+
+    /// Add a link to the link table, returning its value for `Block::link`
+    fn add_link(&mut self, link: Link) -> usize {
+        if let Some(i) = self.links.iter().position(|l| l.as_ref() == Some(&link)) {
+            return i + 1;
+        }
+
+        if !self.links.iter().any(|l| l.is_none()) && self.links.len() >= MAX_LINKS {
+            let mut used = vec![false; self.links.len()];
+            for block in self.grid.iter().chain(self.alt_grid.iter()) {
+                if block.link != 0 {
+                    used[block.link - 1] = true;
+                }
+            }
+            for (l, used) in self.links.iter_mut().zip(used) {
+                if !used {
+                    *l = None;
+                }
+            }
+        }
+
+        match self.links.iter().position(|l| l.is_none()) {
+            Some(i) => {
+                self.links[i] = Some(link);
+                i + 1
+            }
+            None => {
+                self.links.push(Some(link));
+                self.links.len()
+            }
+        }
+    }
+
+    /// Grid index under the mouse
+    fn mouse_index(&self) -> Option<usize> {
+        let x = self.mouse_x as usize;
+        let y = self.mouse_y as usize;
+        if x == 0 || y == 0 || x > self.ransid.state.w || y > self.ransid.state.h {
+            return None;
+        }
+        Some((y - 1) * self.ransid.state.w + (x - 1))
+    }
+
+    /// Grid indices of the link under the mouse
+    fn hover_range(&self) -> Option<Range<usize>> {
+        self.mouse_index()
+            .and_then(|i| self.link_at(i))
+            .map(|(range, _link)| range)
+    }
+
+    fn open_link(&self, link: &str) {
+        // Links can come from applications. xdg-open refuses `--`, so the scheme is what keeps a
+        // link from being read as an option.
+        if !url::has_scheme(link) {
+            eprintln!("orbterm: not opening {:?}, it has no scheme", link);
+            return;
+        }
+        let mut args = self.config.links.opener.split_whitespace();
+        let program = match args.next() {
+            Some(program) => program,
+            None => return,
+        };
+        match process::Command::new(program).args(args).arg(link).spawn() {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => eprintln!("orbterm: failed to run {:?}: {}", program, err),
+        }
+    }
+
+    /// Paste text, asking for confirmation first if it could run commands
+    pub fn paste(&mut self, text: &str) {
+        let bracketed = self.parser.state.bracketed_paste;
+        if self.config.paste.confirm && (!bracketed || self.config.paste.confirm_bracketed) {
+            if let Some(pending_paste) = PendingPaste::new(text) {
+                self.pending_paste = Some(pending_paste);
+                self.sync();
+                return;
+            }
+        }
+
+        self.send_paste(text);
+    }
+
+    /// Send pasted text, wrapped in bracketed paste markers if the application requested them
+    fn send_paste(&mut self, text: &str) {
+        if self.parser.state.bracketed_paste {
+            // Without escapes the text cannot contain an end marker and leave the bracket early
+            let text = text.replace('\x1B', "");
+            self.input.extend_from_slice(b"\x1B[200~");
+            self.input.extend_from_slice(text.as_bytes());
+            self.input.extend_from_slice(b"\x1B[201~");
+        } else {
+            self.input.extend_from_slice(text.as_bytes());
+        }
+    }
+
+    /// Handle input while a paste is waiting for confirmation, returns false if the event should
+    /// be handled as usual
+    fn paste_input(&mut self, event_option: EventOption) -> bool {
+        let mut pending_paste = match self.pending_paste.take() {
+            Some(pending_paste) => pending_paste,
+            None => return false,
+        };
+
+        let mut done = false;
+        match event_option {
+            EventOption::TextInput(text_event) => match text_event.character {
+                's' | 'S' => pending_paste.strip = !pending_paste.strip,
+                'n' | 'N' => pending_paste.join = !pending_paste.join,
+                _ => (),
+            },
+            EventOption::Key(key_event) => match key_event.scancode {
+                // Let modifiers through, so their state stays correct
+                0x1D | orbclient::K_LEFT_SHIFT | orbclient::K_RIGHT_SHIFT | orbclient::K_ALT => {
+                    self.pending_paste = Some(pending_paste);
+                    return false;
+                }
+                orbclient::K_ENTER if key_event.pressed => {
+                    let text = pending_paste.converted();
+                    self.send_paste(&text);
+                    done = true;
+                }
+                orbclient::K_ESC if key_event.pressed => {
+                    done = true;
+                }
+                _ => (),
+            },
+            _ => {
+                self.pending_paste = Some(pending_paste);
+                return false;
+            }
+        }
+
+        if done {
+            // Some platforms follow the key with text input
+            self.skip_text = true;
+            self.repaint();
+        } else {
+            self.pending_paste = Some(pending_paste);
+        }
+        self.sync();
+
+        true
+    }
+
+    /// Handle input while in copy mode, returns false if the event should be handled normally
+    fn copy_mode_input(&mut self, event_option: EventOption) -> bool {
+        let mut copy_mode = match self.copy_mode.take() {
+            Some(copy_mode) => copy_mode,
+            None => return false,
+        };
+        let searching = copy_mode.search.is_some();
+
+        let command = {
+            let screen = Screen {
+                grid: &self.grid,
+                w: self.ransid.state.w,
+                h: self.ransid.state.h,
+                top: self.scrolled,
+                word_separators: &self.config.selection.word_separators,
+            };
+            match event_option {
+                EventOption::TextInput(_) if self.skip_text => {
+                    self.skip_text = false;
+                    Command::None
+                }
+                EventOption::TextInput(text_event) => {
+                    copy_mode.text(text_event.character, self.ctrl, &screen)
+                }
+                EventOption::Key(key_event) => match key_event.scancode {
+                    // Let modifiers through, so their state stays correct
+                    0x1D
+                    | orbclient::K_LEFT_SHIFT
+                    | orbclient::K_RIGHT_SHIFT
+                    | orbclient::K_ALT => {
+                        self.copy_mode = Some(copy_mode);
+                        return false;
                     }
+                    scancode if key_event.pressed => {
+                        // Some platforms follow these keys with text input
+                        self.skip_text =
+                            scancode == orbclient::K_ESC || scancode == orbclient::K_ENTER;
+                        copy_mode.key(scancode, &screen)
+                    }
+                    _ => Command::None,
+                },
+                _ => {
+                    self.copy_mode = Some(copy_mode);
+                    return false;
+                }
+            }
+        };
+
+        match command {
+            Command::None => {
+                self.selection = Some(copy_mode.selection(self.ransid.state.w));
+                self.copy_mode = Some(copy_mode);
+            }
+            Command::Yank => {
+                self.selection = Some(copy_mode.selection(self.ransid.state.w));
+                let text = self.selection_text();
+                self.window.set_clipboard(&text, MediaKind::Text);
+                self.selection = None;
+            }
+            Command::Exit => {
+                self.selection = None;
+            }
+        }
+
+        let prompt_closed = match self.copy_mode {
+            Some(ref copy_mode) => searching && copy_mode.search.is_none(),
+            None => searching,
+        };
+        if prompt_closed {
+            self.repaint();
+        }
+        self.write(&[], true)
+            .expect("failed to write empty buffer after updating selection");
+
+        true
+    }
+
+    /// Label the matches of the hint rules on the screen
+    fn show_hints(&mut self) {
+        let w = self.ransid.state.w;
+        let mut matches = Vec::new();
+        let mut y = 0;
+        while y < self.ransid.state.h {
+            let (start, end) = self.line_at(y * w);
+            let chars: Vec<char> = self.grid[start..end]
+                .iter()
+                .map(|block| if block.c == '\0' { ' ' } else { block.c })
+                .collect();
+            for (range, action, text) in self.hint_rules.find(&chars) {
+                matches.push((start + range.start..start + range.end, action, text));
+            }
+            y = end / w;
+        }
+
+        let alphabet: Vec<char> = self.config.hints.alphabet.chars().collect();
+        let labels = hints::labels(&alphabet, matches.len());
+        if matches.is_empty() || labels.is_empty() {
+            return;
+        }
+
+        let hints = matches
+            .into_iter()
+            .zip(labels)
+            .map(|((range, action, text), label)| Hint {
+                range,
+                label,
+                action,
+                text,
+            })
+            .collect();
+        self.hints = Some(Hints {
+            hints,
+            typed: String::new(),
+        });
+        self.sync();
+    }
+
+    /// Handle input while hints are shown, returns false if the event should be handled normally
+    fn hints_input(&mut self, event_option: EventOption) -> bool {
+        let mut hints = match self.hints.take() {
+            Some(hints) => hints,
+            None => return false,
+        };
+
+        let mut done = false;
+        match event_option {
+            EventOption::TextInput(_) if self.skip_text => {
+                self.skip_text = false;
+            }
+            EventOption::TextInput(text_event) => {
+                hints.typed.push(text_event.character);
+                if hints.visible().is_empty() {
+                    hints.typed.pop();
+                }
+            }
+            EventOption::Key(key_event) => match key_event.scancode {
+                // Let modifiers through, so their state stays correct
+                0x1D | orbclient::K_LEFT_SHIFT | orbclient::K_RIGHT_SHIFT | orbclient::K_ALT => {
+                    self.hints = Some(hints);
+                    return false;
+                }
+                orbclient::K_BKSP if key_event.pressed => {
+                    hints.typed.pop();
                 }
+                orbclient::K_ESC if key_event.pressed => {
+                    // Some platforms follow the key with text input
+                    self.skip_text = true;
+                    done = true;
+                }
+                _ if key_event.pressed => {
+                    self.skip_text = false;
+                }
+                _ => (),
+            },
+            _ => {
+                self.hints = Some(hints);
+                return false;
+            }
+        }
+
+        if let Some(hint) = hints.chosen() {
+            match hint.action {
+                HintAction::Copy => {
+                    self.window.set_clipboard(&hint.text, MediaKind::Text);
+                }
+                HintAction::Open => self.open_link(&hint.text),
+                HintAction::Send => self.input.extend(hint.text.as_bytes()),
+            }
+            done = true;
+        }
+
+        // Remove the labels of hints that no longer match
+        self.repaint();
+        if !done {
+            self.hints = Some(hints);
+        }
+        self.sync();
+
+        true
+    }
+
+    /// Draw the labels of hints matching what has been typed
+    fn draw_hints(&mut self) {
+        let labels: Vec<(usize, String)> = match self.hints {
+            Some(ref hints) => hints
+                .visible()
+                .iter()
+                .map(|hint| (hint.range.start, hint.label.clone()))
+                .collect(),
+            None => return,
+        };
+
+        for (start, label) in labels {
+            for (j, c) in label.chars().enumerate() {
+                let i = start + j;
+                let x = i % self.ransid.state.w;
+                let y = i / self.ransid.state.w;
+                if y >= self.ransid.state.h {
+                    break;
+                }
+
+                self.window.mode().set(Mode::Overwrite);
+                self.window.rect(
+                    (x * self.block_width) as i32,
+                    (y * self.block_height) as i32,
+                    self.block_width as u32,
+                    self.block_height as u32,
+                    Color::rgb(0xFF, 0xCC, 0x00),
+                );
+                self.window.mode().set(Mode::Blend);
+
+                let mut str_buf = [0; 4];
+                self.font_bold
+                    .render(c.encode_utf8(&mut str_buf), self.block_height as f32)
+                    .draw(
+                        &mut self.window,
+                        (x * self.block_width) as i32,
+                        (y * self.block_height) as i32,
+                        Color::rgb(0x00, 0x00, 0x00),
+                    );
+                self.changed.insert(y);
+            }
+        }
+    }
+
+    fn modifiers(&self) -> keyboard::Modifiers {
+        keyboard::Modifiers {
+            shift: self.shift,
+            alt: self.alt,
+            ctrl: self.ctrl,
+        }
+    }
+
+    /// Encode a key using the kitty keyboard protocol, if it was enabled by the application
+    fn kitty_key(&self, scancode: u8, pressed: bool) -> Option<Vec<u8>> {
+        // Keep the key bindings of the terminal itself
+        if self.bindings.get(scancode, self.modifiers()).is_some() {
+            return None;
+        }
+
+        keyboard::encode(
+            scancode,
+            pressed,
+            self.modifiers(),
+            self.parser.state.keyboard,
+        )
+    }
+
+    /// Run a built-in action from a key binding
+    fn action(&mut self, action: Action) {
+        match action {
+            Action::Copy => {
+                let text = self.selection_text();
+                self.window.set_clipboard(&text, MediaKind::Text);
+            }
+            Action::CopyHtml => {
+                let html = export::html(&self.selection_rows());
+                self.window.set_clipboard(&html, MediaKind::Text);
+            }
+            Action::CopyAnsi => {
+                let ansi = export::ansi(&self.selection_rows());
+                self.window.set_clipboard(&ansi, MediaKind::Text);
+            }
+            Action::Paste => {
+                let text = self
+                    .window
+                    .clipboard()
+                    .map_or(String::new(), |(_kind, text)| text);
+                self.paste(&text);
+            }
+            Action::ZoomIn => {
+                let new_block_width = self.block_width + 1;
+                self.set_block_size(new_block_width);
+                self.apply_zoom();
+            }
+            Action::ZoomOut => {
+                let new_block_width = self.block_width - 1;
+                self.set_block_size(new_block_width);
+                self.apply_zoom();
+            }
+            Action::ZoomReset => {
+                self.block_width = self.default_block_width;
+                self.block_height = self.default_block_height;
+                self.apply_zoom();
+            }
+            Action::ScrollUp | Action::ScrollDown => {
+                let up = action == Action::ScrollUp;
+                if self.ransid.state.mouse_rxvt {
+                    self.report_scroll(up);
+                } else if self.alternate {
+                    // Like the alternate scroll mode of xterm
+                    let key: &[u8] = match (up, self.parser.state.cursor_keys) {
+                        (true, false) => b"\x1B[A",
+                        (true, true) => b"\x1BOA",
+                        (false, false) => b"\x1B[B",
+                        (false, true) => b"\x1BOB",
+                    };
+                    self.input.extend_from_slice(key);
+                }
+            }
+            Action::CopyMode | Action::Search | Action::PreviousPrompt | Action::NextPrompt => {
+                let w = self.ransid.state.w;
+                let x = cmp::min(self.ransid.state.x, w - 1);
+                let cursor = self.point(self.ransid.state.y * w + x);
+                let mut copy_mode = CopyMode::new(cursor);
+                if action == Action::PreviousPrompt || action == Action::NextPrompt {
+                    let screen = Screen {
+                        grid: &self.grid,
+                        w,
+                        h: self.ransid.state.h,
+                        top: self.scrolled,
+                        word_separators: &self.config.selection.word_separators,
+                    };
+                    copy_mode.find_prompt(action == Action::NextPrompt, &screen);
+                }
+                if action == Action::Search {
+                    copy_mode.search = Some(String::new());
+                }
+                self.selection = Some(copy_mode.selection(w));
+                self.copy_mode = Some(copy_mode);
+                self.write(&[], true)
+                    .expect("failed to write empty buffer after updating selection");
+            }
+            Action::SelectOutput => {
+                if let Some((selection, _scrolled)) = self.last_output() {
+                    self.selection = Some(selection);
+                    // Like a mouse selection, the selected text can be pasted with a middle click
+                    let text = self.selection_text();
+                    if !text.is_empty() {
+                        self.primary = text;
+                    }
+                    self.write(&[], true)
+                        .expect("failed to write empty buffer after updating selection");
+                }
+            }
+            Action::CopyOutput => {
+                if let Some((selection, scrolled)) = self.last_output() {
+                    let mut rows = Vec::new();
+                    if scrolled {
+                        rows.extend(self.scrolled_output.iter().cloned());
+                    }
+                    rows.extend(self.rows(&selection));
+                    let text = export::plain(&rows);
+                    self.window.set_clipboard(&text, MediaKind::Text);
+                }
+            }
+            Action::Hints => self.show_hints(),
+            Action::NewWindow => {
+                let exe = match env::current_exe() {
+                    Ok(exe) => exe,
+                    Err(err) => {
+                        eprintln!("orbterm: failed to find the orbterm executable: {}", err);
+                        return;
+                    }
+                };
+                let mut command = process::Command::new(exe);
+                if let Some(ref cwd) = self.cwd {
+                    command.current_dir(cwd);
+                }
+                match command.spawn() {
+                    Ok(mut child) => {
+                        thread::spawn(move || child.wait());
+                    }
+                    Err(err) => eprintln!("orbterm: failed to open a new window: {}", err),
+                }
+            }
+            Action::Reset => {
+                self.write(b"\x1Bc", true)
+                    .expect("failed to write reset to console");
             }
         }
-        string
     }
 
     pub fn input(&mut self, event_option: EventOption) {
+        if self.paste_input(event_option)
+            || self.copy_mode_input(event_option)
+            || self.hints_input(event_option)
+        {
+            return;
+        }
+
         let mut next_selection = self.selection;
+        let mut redraw = false;
         match event_option {
+            EventOption::TextInput(_) if self.skip_text => {
+                // Already sent by the Key event
+                self.skip_text = false;
+            }
             EventOption::TextInput(key_event) => {
                 let mut buf = vec![];
 
                 let c = match key_event.character {
-                    // FIXME fix ctrl commands. They currently only produce a Key event, but that
-                    // doesn't contain which character was entered.
-
-                    // Copy with ctrl-shift-c
-                    'C' if self.ctrl => {
-                        let text = self.selection_text();
-                        self.window.set_clipboard(&text, MediaKind::Text);
-                        '\0'
-                    }
-                    // Paste with ctrl-shift-v
-                    'V' if self.ctrl => {
-                        buf.extend_from_slice(
-                            &self
-                                .window
-                                .clipboard()
-                                .map_or(String::new(), |(_kind, text)| text)
-                                .as_bytes(),
-                        );
-                        '\0'
-                    }
                     c @ 'A'..='Z' if self.ctrl => ((c as u8 - b'A') + b'\x01') as char,
                     c @ 'a'..='z' if self.ctrl => ((c as u8 - b'a') + b'\x01') as char,
+                    // Ctrl with symbols and digits is handled by the Key event, as some platforms
+                    // only produce a Key event for them
+                    '@' | '[' | '\\' | ']' | '^' | '_' | '/' | '?' | ' ' | '2'..='8'
+                        if self.ctrl =>
+                    {
+                        '\0'
+                    }
                     c => c,
                 };
 
@@ -183,56 +1123,106 @@ impl Console {
 
                 if key_event.scancode == 0x1D {
                     self.ctrl = key_event.pressed;
-                } else if key_event.pressed {
-                    match key_event.scancode {
-                        orbclient::K_0 if self.ctrl => {
-                            // Ctrl-0 reset block size
-                            self.block_width = self.default_block_width;
-                            self.block_height = self.default_block_height;
+                } else if key_event.scancode == orbclient::K_LEFT_SHIFT
+                    || key_event.scancode == orbclient::K_RIGHT_SHIFT
+                {
+                    self.shift = key_event.pressed;
+                } else if key_event.scancode == orbclient::K_ALT {
+                    self.alt = key_event.pressed;
+                }
 
-                            let w = self.window.width() as usize / self.block_width;
-                            let h = self.window.height() as usize / self.block_height;
+                let binding = if key_event.pressed {
+                    self.bindings
+                        .get(key_event.scancode, self.modifiers())
+                        .cloned()
+                } else {
+                    None
+                };
 
-                            self.resize_grid(w, h);
-                            self.sync();
+                if let Some(binding) = binding {
+                    match binding {
+                        Binding::Action(action) => {
+                            self.action(action);
+                            next_selection = self.selection;
                         }
-                        orbclient::K_MINUS if self.ctrl => {
-                            // Ctrl-Minus increase block size
-                            let new_block_width = self.block_width - 1;
-                            self.set_block_size(new_block_width);
+                        Binding::Send(data) => buf.extend(data),
+                    }
+                    self.skip_text = true;
+                } else if let Some(data) = self.kitty_key(key_event.scancode, key_event.pressed) {
+                    buf.extend(data);
+                    self.skip_text = key_event.pressed;
+                } else if key_event.pressed {
+                    self.skip_text = false;
 
-                            let w = self.window.width() as usize / self.block_width;
-                            let h = self.window.height() as usize / self.block_height;
+                    // Cursor keys use SS3 instead of CSI in application mode
+                    let cursor: &[u8] = if self.parser.state.cursor_keys {
+                        b"\x1BO"
+                    } else {
+                        b"\x1B["
+                    };
 
-                            self.resize_grid(w, h);
-                            self.sync();
+                    match key_event.scancode {
+                        orbclient::K_2 | orbclient::K_SPACE if self.ctrl => {
+                            // Ctrl-@, Ctrl-2 and Ctrl-Space send NUL
+                            buf.push(b'\x00');
                         }
-                        orbclient::K_EQUALS if self.ctrl => {
-                            // Ctrl-Plus decrease block size
-                            let new_block_width = self.block_width + 1;
-                            self.set_block_size(new_block_width);
-
-                            let w = self.window.width() as usize / self.block_width;
-                            let h = self.window.height() as usize / self.block_height;
-
-                            self.resize_grid(w, h);
-                            self.sync();
+                        orbclient::K_3 | orbclient::K_BRACE_OPEN if self.ctrl => {
+                            // Ctrl-[ and Ctrl-3 send ESC
+                            buf.push(b'\x1B');
+                        }
+                        orbclient::K_4 | orbclient::K_BACKSLASH if self.ctrl => {
+                            // Ctrl-\ and Ctrl-4 send FS
+                            buf.push(b'\x1C');
+                        }
+                        orbclient::K_5 | orbclient::K_BRACE_CLOSE if self.ctrl => {
+                            // Ctrl-] and Ctrl-5 send GS
+                            buf.push(b'\x1D');
+                        }
+                        orbclient::K_6 if self.ctrl => {
+                            // Ctrl-^ and Ctrl-6 send RS
+                            buf.push(b'\x1E');
+                        }
+                        orbclient::K_SLASH if self.ctrl && self.shift => {
+                            // Ctrl-? sends DEL
+                            buf.push(b'\x7F');
+                        }
+                        orbclient::K_7 | orbclient::K_MINUS | orbclient::K_SLASH if self.ctrl => {
+                            // Ctrl-_, Ctrl-/ and Ctrl-7 send US
+                            buf.push(b'\x1F');
+                        }
+                        orbclient::K_8 if self.ctrl => {
+                            // Ctrl-8 sends DEL
+                            buf.push(b'\x7F');
                         }
                         orbclient::K_ENTER => {
                             // Enter
                             buf.extend_from_slice(b"\r");
                         }
+                        orbclient::K_NUM_ENTER => {
+                            // Keypad enter
+                            if self.parser.state.keypad {
+                                buf.extend_from_slice(b"\x1BOM");
+                            } else {
+                                buf.extend_from_slice(b"\r");
+                            }
+                        }
                         orbclient::K_BKSP => {
                             // Backspace
-                            buf.extend_from_slice(b"\x7F");
+                            if self.parser.state.backarrow {
+                                buf.extend_from_slice(b"\x08");
+                            } else {
+                                buf.extend_from_slice(b"\x7F");
+                            }
                         }
                         orbclient::K_HOME => {
                             // Home
-                            buf.extend_from_slice(b"\x1B[H");
+                            buf.extend_from_slice(cursor);
+                            buf.push(b'H');
                         }
                         orbclient::K_UP => {
                             // Up
-                            buf.extend_from_slice(b"\x1B[A");
+                            buf.extend_from_slice(cursor);
+                            buf.push(b'A');
                         }
                         orbclient::K_PGUP => {
                             // Page up
@@ -240,19 +1230,23 @@ impl Console {
                         }
                         orbclient::K_LEFT => {
                             // Left
-                            buf.extend_from_slice(b"\x1B[D");
+                            buf.extend_from_slice(cursor);
+                            buf.push(b'D');
                         }
                         orbclient::K_RIGHT => {
                             // Right
-                            buf.extend_from_slice(b"\x1B[C");
+                            buf.extend_from_slice(cursor);
+                            buf.push(b'C');
                         }
                         orbclient::K_END => {
                             // End
-                            buf.extend_from_slice(b"\x1B[F");
+                            buf.extend_from_slice(cursor);
+                            buf.push(b'F');
                         }
                         orbclient::K_DOWN => {
                             // Down
-                            buf.extend_from_slice(b"\x1B[B");
+                            buf.extend_from_slice(cursor);
+                            buf.push(b'B');
                         }
                         orbclient::K_PGDN => {
                             // Page down
@@ -264,7 +1258,38 @@ impl Console {
                         }
                         orbclient::K_DEL => {
                             // Delete
-                            buf.extend_from_slice(b"\x1B[3~");
+                            match self.config.delete {
+                                Delete::Vt220 => buf.extend_from_slice(b"\x1B[3~"),
+                                Delete::Del => buf.extend_from_slice(b"\x7F"),
+                            }
+                        }
+                        // Application keypad
+                        scancode @ orbclient::K_NUM_0..=orbclient::K_NUM_9
+                            if self.parser.state.keypad =>
+                        {
+                            buf.extend_from_slice(b"\x1BO");
+                            buf.push(b'p' + (scancode - orbclient::K_NUM_0));
+                            self.skip_text = true;
+                        }
+                        orbclient::K_NUM_PERIOD if self.parser.state.keypad => {
+                            buf.extend_from_slice(b"\x1BOn");
+                            self.skip_text = true;
+                        }
+                        orbclient::K_NUM_PLUS if self.parser.state.keypad => {
+                            buf.extend_from_slice(b"\x1BOk");
+                            self.skip_text = true;
+                        }
+                        orbclient::K_NUM_MINUS if self.parser.state.keypad => {
+                            buf.extend_from_slice(b"\x1BOm");
+                            self.skip_text = true;
+                        }
+                        orbclient::K_NUM_ASTERISK if self.parser.state.keypad => {
+                            buf.extend_from_slice(b"\x1BOj");
+                            self.skip_text = true;
+                        }
+                        orbclient::K_NUM_SLASH if self.parser.state.keypad => {
+                            buf.extend_from_slice(b"\x1BOo");
+                            self.skip_text = true;
                         }
                         // Function keys
                         orbclient::K_F1 => {
@@ -319,254 +1344,646 @@ impl Console {
                             self.input.extend(string.as_bytes());
                         }
                     }
-                } else if self.mouse_left {
-                    let i = (y as usize - 1) * self.ransid.state.w as usize + (x as usize - 1);
-                    next_selection = match self.selection {
-                        Some(selection) => Some((selection.0, i)),
-                        None => Some((i, i)),
+                } else if self.mouse_left {
+                    let i = (y as usize - 1) * self.ransid.state.w as usize + (x as usize - 1);
+                    next_selection = if self.click_count > 1 {
+                        // Extend by whole words or lines
+                        let unit = self.select_unit(i);
+                        Some(Selection::new(
+                            cmp::min(self.selection_anchor.0, unit.0),
+                            cmp::max(self.selection_anchor.1, unit.1),
+                        ))
+                    } else {
+                        match self.selection {
+                            Some(selection) => Some(Selection {
+                                end: self.point(i),
+                                ..selection
+                            }),
+                            None => Some(Selection::new(self.point(i), self.point(i))),
+                        }
+                    };
+                }
+                self.mouse_x = x;
+                self.mouse_y = y;
+
+                if self.hover_range() != self.hover {
+                    redraw = true;
+                }
+            }
+            EventOption::Button(button_event) => {
+                let x = self.mouse_x;
+                let y = self.mouse_y;
+                let link = if self.ctrl && button_event.left && !self.mouse_left {
+                    self.mouse_index().and_then(|i| self.link_at(i))
+                } else {
+                    None
+                };
+                if let Some((_range, link)) = link {
+                    self.open_link(&link);
+                } else if self.ransid.state.mouse_rxvt {
+                    if button_event.left {
+                        if !self.mouse_left {
+                            let string = format!("\x1B[<{};{};{}M", 0, x, y);
+                            self.input.extend(string.as_bytes());
+                        }
+                    } else if self.mouse_left {
+                        let string = format!("\x1B[<{};{};{}m", 0, x, y);
+                        self.input.extend(string.as_bytes());
+                    }
+                } else if button_event.left && !self.mouse_left {
+                    let i = (y as usize - 1) * self.ransid.state.w as usize + (x as usize - 1);
+                    let now = Instant::now();
+                    self.click_count = match self.last_click {
+                        Some((time, last))
+                            if last == i
+                                && now.duration_since(time) < MULTI_CLICK
+                                && self.click_count < 3 =>
+                        {
+                            self.click_count + 1
+                        }
+                        _ => 1,
+                    };
+                    if self.alt {
+                        // Alt+drag selects a rectangle
+                        self.click_count = 1;
+                    }
+                    self.last_click = Some((now, i));
+                    self.selection_anchor = self.select_unit(i);
+                    next_selection = Some(Selection {
+                        start: self.selection_anchor.0,
+                        end: self.selection_anchor.1,
+                        block: self.alt,
+                    });
+                } else if !button_event.left && self.mouse_left {
+                    // Like the X11 primary selection, releasing the button makes the selection
+                    // available for middle click pasting
+                    let text = self.selection_text();
+                    if !text.is_empty() {
+                        if self.config.selection.copy_on_select {
+                            self.window.set_clipboard(&text, MediaKind::Text);
+                        }
+                        self.primary = text;
+                    }
+                }
+
+                if !self.ransid.state.mouse_rxvt && button_event.middle && !self.mouse_middle {
+                    let text = self.primary.clone();
+                    if !text.is_empty() {
+                        self.paste(&text);
+                    }
+                }
+
+                self.mouse_left = button_event.left;
+                self.mouse_middle = button_event.middle;
+            }
+            EventOption::Scroll(scroll_event) => {
+                if self.ctrl {
+                    let new_block_width =
+                        (self.block_width as i32 + scroll_event.y.signum()) as usize;
+                    self.set_block_size(new_block_width);
+
+                    let w = self.window.width() as usize / self.block_width;
+                    let h = self.window.height() as usize / self.block_height;
+
+                    self.resize_grid(w, h);
+                    self.sync();
+                } else if self.ransid.state.mouse_rxvt && scroll_event.y != 0 {
+                    self.report_scroll(scroll_event.y > 0);
+                }
+            }
+            EventOption::Focus(focus_event) => {
+                self.focused = focus_event.focused;
+                if self.focused && self.attention {
+                    self.attention = false;
+                    self.update_title();
+                }
+            }
+            EventOption::Resize(resize_event) => {
+                let w = resize_event.width as usize / self.block_width;
+                let h = resize_event.height as usize / self.block_height;
+                self.resize_grid(w, h);
+                self.sync();
+            }
+            _ => (),
+        }
+
+        if next_selection != self.selection || redraw {
+            self.selection = next_selection;
+            self.write(&[], true)
+                .expect("failed to write empty buffer after updating selection");
+        }
+    }
+
+    /// Report a step of the mouse wheel to an application that tracks the mouse
+    fn report_scroll(&mut self, up: bool) {
+        let button = if up { 64 } else { 65 };
+        let string = format!("\x1B[<{};{};{}M", button, self.mouse_x, self.mouse_y);
+        self.input.extend(string.as_bytes());
+    }
+
+    pub fn invert(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        let width = self.window.width() as usize;
+        let height = self.window.height() as usize;
+
+        let start_y = cmp::min(height - 1, y);
+        let end_y = cmp::min(height - 1, y + h);
+
+        let start_x = cmp::min(width - 1, x);
+        let len = cmp::min(width - 1, x + w) - start_x;
+
+        let mut offscreen_ptr = self.window.data_mut().as_mut_ptr() as usize;
+
+        let stride = width * 4;
+
+        let offset = y * stride + start_x * 4;
+        offscreen_ptr += offset;
+
+        let mut rows = end_y - start_y;
+        while rows > 0 {
+            let mut row_ptr = offscreen_ptr;
+            let mut cols = len;
+            while cols > 0 {
+                unsafe {
+                    let color = *(row_ptr as *mut u32);
+                    *(row_ptr as *mut u32) = color ^ 0x00FFFFFF;
+                }
+                row_ptr += 4;
+                cols -= 1;
+            }
+            offscreen_ptr += stride;
+            rows -= 1;
+        }
+    }
+
+    /// Show or hide the cursor, which inverts its block unless it has a color
+    fn draw_cursor(&mut self, visible: bool) {
+        let x = self.ransid.state.x;
+        let y = self.ransid.state.y;
+        if !self.ransid.state.cursor || x >= self.ransid.state.w || y >= self.ransid.state.h {
+            return;
+        }
+
+        match self.palette.cursor {
+            Some(rgb) if visible => {
+                let fg = match self.grid.get(y * self.ransid.state.w + x) {
+                    Some(block) => block.bg,
+                    None => return,
+                };
+                let bg = Color {
+                    data: ((self.alpha as u32) << 24) | rgb,
+                };
+                self.draw_block(x, y, Some((fg, bg)));
+            }
+            Some(_) => self.draw_block(x, y, None),
+            None => {
+                let block_width = self.block_width;
+                let block_height = self.block_height;
+                self.invert(x * block_width, y * block_height, block_width, block_height);
+            }
+        }
+        self.changed.insert(y);
+    }
+
+    /// Resolve the colors of all blocks again after the palette changed, and redraw them
+    fn recolor(&mut self) {
+        let alpha = self.alpha;
+        let palette = &self.palette;
+        let cvt = |rgb: u32| -> Color {
+            Color {
+                data: ((alpha as u32) << 24) | rgb,
+            }
+        };
+
+        for block in self.grid.iter_mut().chain(self.alt_grid.iter_mut()) {
+            block.fg = cvt(palette.fg(block.raw_fg));
+            block.bg = cvt(palette.bg(block.raw_bg));
+        }
+        self.window.set(cvt(palette.background));
+
+        for y in 0..self.ransid.state.h {
+            for x in 0..self.ransid.state.w {
+                self.draw_block(x, y, None);
+            }
+            self.changed.insert(y);
+        }
+    }
+
+    /// Index of the block under the cursor
+    fn cursor_index(&self) -> usize {
+        let w = self.ransid.state.w;
+        self.ransid.state.y * w + cmp::min(self.ransid.state.x, w - 1)
+    }
+
+    /// Mark the block under the cursor, so the mark moves along with the text
+    fn set_mark(&mut self, mark: Mark) {
+        let i = self.cursor_index();
+        if let Some(block) = self.grid.get_mut(i) {
+            block.mark = Some(mark);
+        }
+    }
+
+    /// Draw a bar in the margin of changed rows that have the prompt of a failed command
+    fn draw_marks(&mut self) {
+        let w = self.ransid.state.w;
+        let h = self.ransid.state.h;
+        let thickness = cmp::max(2, self.block_width / 4);
+        let color = Color {
+            data: ((self.alpha as u32) << 24) | self.palette.colors[1],
+        };
+        let rows: Vec<usize> = self.changed.iter().cloned().filter(|&y| y < h).collect();
+        for y in rows {
+            let failed = self.grid[y * w..(y + 1) * w]
+                .iter()
+                .any(|block| match block.mark {
+                    Some(Mark::Prompt { exit: Some(exit) }) => exit != 0,
+                    _ => false,
+                });
+            if failed {
+                self.window.mode().set(Mode::Overwrite);
+                self.window.rect(
+                    0,
+                    (y * self.block_height) as i32,
+                    thickness as u32,
+                    self.block_height as u32,
+                    color,
+                );
+                self.window.mode().set(Mode::Blend);
+            }
+        }
+    }
+
+    /// Draw the blocks of a selection, with the selection colors or their own
+    fn draw_selection(&mut self, selection: Selection, selected: bool) {
+        let colors = if selected {
+            Some((self.selection_fg, self.selection_bg))
+        } else {
+            None
+        };
+        for range in self.selection_ranges(&selection) {
+            for i in range {
+                let x = i % self.ransid.state.w;
+                let y = i / self.ransid.state.w;
+                self.draw_block(x, y, colors);
+                self.changed.insert(y);
+            }
+        }
+    }
+
+    pub fn write(&mut self, buf: &[u8], sync: bool) -> Result<usize> {
+        self.draw_cursor(false);
+
+        if let Some(selection) = self.last_selection {
+            self.draw_selection(selection, false);
+        }
+
+        if let Some(hover) = self.hover.take() {
+            for i in hover {
+                let x = i % self.ransid.state.w;
+                let y = i / self.ransid.state.w;
+                self.draw_block(x, y, None);
+                self.changed.insert(y);
+            }
+        }
+
+        let mut events = Vec::new();
+        self.parser
+            .write(buf, |offset, event| events.push((offset, event)));
+
+        // Events are handled before ransid sees the byte that completed their sequence
+        let mut start = 0;
+        let mut recolor = false;
+        for (offset, event) in events {
+            if start < offset {
+                self.write_ransid(&buf[start..offset]);
+                start = offset;
+            }
+
+            match event {
+                parser::Event::Input { data } => {
+                    self.input.extend(data);
+                }
+                parser::Event::WorkingDirectory { host, path } => {
+                    // A directory on another host, like over ssh, cannot be opened here
+                    self.cwd = if is_local_host(&host) {
+                        Some(path)
+                    } else {
+                        None
+                    };
+                    self.update_title();
+                }
+                parser::Event::Progress { progress } => {
+                    if progress != self.progress {
+                        // Remove the bar, it is drawn again on sync
+                        for x in 0..self.ransid.state.w {
+                            self.draw_block(x, 0, None);
+                        }
+                        self.changed.insert(0);
+                        self.progress = progress;
+                        self.update_title();
+                    }
+                }
+                parser::Event::Notify { title, body } => self.notify(title, body),
+                parser::Event::Hyperlink { uri, id } => {
+                    self.link = match uri {
+                        Some(uri) => self.add_link(Link { uri, id }),
+                        None => 0,
+                    };
+                }
+                parser::Event::SetClipboard { primary, text } => {
+                    if self.config.clipboard.allow_write {
+                        if primary {
+                            self.primary = text;
+                        } else {
+                            self.window.set_clipboard(&text, MediaKind::Text);
+                        }
+                    }
+                }
+                parser::Event::QueryClipboard { primary, bell } => {
+                    if self.config.clipboard.allow_read {
+                        let text = if primary {
+                            self.primary.clone()
+                        } else {
+                            self.window
+                                .clipboard()
+                                .map_or(String::new(), |(_kind, text)| text)
+                        };
+                        let target = if primary { 'p' } else { 'c' };
+                        let reply = format!(
+                            "\x1B]52;{};{}{}",
+                            target,
+                            base64::encode(text.as_bytes()),
+                            if bell { "\x07" } else { "\x1B\\" }
+                        );
+                        self.input.extend(reply.as_bytes());
+                    }
+                }
+                parser::Event::PromptStart => self.set_mark(Mark::Prompt { exit: None }),
+                parser::Event::CommandStart => self.set_mark(Mark::Command),
+                parser::Event::OutputStart => self.set_mark(Mark::Output),
+                parser::Event::CommandEnd { exit } => {
+                    let cursor = self.cursor_index();
+                    self.set_mark(Mark::End);
+                    let prompt = self.grid[..cursor]
+                        .iter()
+                        .rposition(|block| matches!(block.mark, Some(Mark::Prompt { .. })));
+                    if let Some(prompt) = prompt {
+                        self.grid[prompt].mark = Some(Mark::Prompt { exit });
+                        self.changed.insert(prompt / self.ransid.state.w);
+                    }
+                }
+                parser::Event::SetColor { target, rgb } => {
+                    self.palette.set(target, rgb);
+                    // The cursor is drawn again anyway
+                    if target != Target::Cursor {
+                        recolor = true;
+                    }
+                }
+                parser::Event::QueryColor { target } => {
+                    let rgb = palette::format(self.palette.get(target));
+                    let reply = match target {
+                        Target::Palette(index) => format!("\x1B]4;{};{}\x1B\\", index, rgb),
+                        Target::Foreground => format!("\x1B]10;{}\x1B\\", rgb),
+                        Target::Background => format!("\x1B]11;{}\x1B\\", rgb),
+                        Target::Cursor => format!("\x1B]12;{}\x1B\\", rgb),
                     };
+                    self.input.extend(reply.as_bytes());
                 }
-                self.mouse_x = x;
-                self.mouse_y = y;
-            }
-            EventOption::Button(button_event) => {
-                let x = self.mouse_x;
-                let y = self.mouse_y;
-                if self.ransid.state.mouse_rxvt {
-                    if button_event.left {
-                        if !self.mouse_left {
-                            let string = format!("\x1B[<{};{};{}M", 0, x, y);
-                            self.input.extend(string.as_bytes());
+                parser::Event::Title {
+                    title,
+                    icon,
+                    window,
+                } => {
+                    if icon {
+                        self.icon = title.clone();
+                    }
+                    if window {
+                        self.title = title;
+                    }
+                    self.update_title();
+                }
+                parser::Event::PushTitle { icon, window } => {
+                    if self.title_stack.len() >= TITLE_STACK {
+                        self.title_stack.remove(0);
+                    }
+                    self.title_stack.push((
+                        icon.then(|| self.icon.clone()),
+                        window.then(|| self.title.clone()),
+                    ));
+                }
+                parser::Event::PopTitle { icon, window } => {
+                    if let Some((saved_icon, saved_title)) = self.title_stack.pop() {
+                        if let (true, Some(saved_icon)) = (icon, saved_icon) {
+                            self.icon = saved_icon;
                         }
-                    } else if self.mouse_left {
-                        let string = format!("\x1B[<{};{};{}m", 0, x, y);
-                        self.input.extend(string.as_bytes());
+                        if let (true, Some(saved_title)) = (window, saved_title) {
+                            self.title = saved_title;
+                        }
+                        self.update_title();
                     }
-                } else if button_event.left && !self.mouse_left {
-                    let i = (y as usize - 1) * self.ransid.state.w as usize + (x as usize - 1);
-                    next_selection = Some((i, i));
                 }
-
-                self.mouse_left = button_event.left;
-            }
-            EventOption::Scroll(scroll_event) => {
-                if self.ctrl {
-                    let new_block_width =
-                        (self.block_width as i32 + scroll_event.y.signum()) as usize;
-                    self.set_block_size(new_block_width);
-
-                    let w = self.window.width() as usize / self.block_width;
-                    let h = self.window.height() as usize / self.block_height;
-
-                    self.resize_grid(w, h);
-                    self.sync();
-                } else if self.ransid.state.mouse_rxvt {
-                    if scroll_event.y > 0 {
-                        let string = format!("\x1B[<{};{};{}M", 64, self.mouse_x, self.mouse_y);
-                        self.input.extend(string.as_bytes());
-                    } else if scroll_event.y < 0 {
-                        let string = format!("\x1B[<{};{};{}M", 65, self.mouse_x, self.mouse_y);
-                        self.input.extend(string.as_bytes());
+                parser::Event::ReportTitle { icon } => {
+                    // The title could have been set by anyone able to write to the terminal
+                    if self.config.title.allow_report {
+                        let reply = if icon {
+                            format!("\x1B]L{}\x1B\\", self.icon)
+                        } else {
+                            format!("\x1B]l{}\x1B\\", self.title)
+                        };
+                        self.input.extend(reply.as_bytes());
                     }
                 }
+                parser::Event::CursorMove => {
+                    self.last_char = None;
+                }
+                parser::Event::KeyboardMode => {
+                    // ransid handles any CSI u as a cursor restore, so keep the cursor in place
+                    let x = self.ransid.state.x;
+                    let y = self.ransid.state.y;
+                    self.write_ransid(&buf[offset..offset + 1]);
+                    start = offset + 1;
+                    self.ransid.state.x = x;
+                    self.ransid.state.y = y;
+                }
             }
-            EventOption::Resize(resize_event) => {
-                let w = resize_event.width as usize / self.block_width;
-                let h = resize_event.height as usize / self.block_height;
-                self.resize_grid(w, h);
-                self.sync();
-            }
-            _ => (),
         }
+        self.write_ransid(&buf[start..]);
 
-        if next_selection != self.selection {
-            self.selection = next_selection;
-            self.write(&[], true)
-                .expect("failed to write empty buffer after updating selection");
+        if recolor {
+            self.recolor();
         }
-    }
-
-    pub fn invert(&mut self, x: usize, y: usize, w: usize, h: usize) {
-        let width = self.window.width() as usize;
-        let height = self.window.height() as usize;
 
-        let start_y = cmp::min(height - 1, y);
-        let end_y = cmp::min(height - 1, y + h);
+        // Programs starting or exiting usually write something, so the foreground process is only
+        // looked up after output
+        if !buf.is_empty() && self.config.title.template.contains("{process}") {
+            let foreground = unsafe { libc::tcgetpgrp(self.master_fd) };
+            if foreground != self.foreground {
+                self.foreground = foreground;
+                self.update_title();
+            }
+        }
 
-        let start_x = cmp::min(width - 1, x);
-        let len = cmp::min(width - 1, x + w) - start_x;
+        if let Some(selection) = self.selection {
+            self.draw_selection(selection, true);
+        }
 
-        let mut offscreen_ptr = self.window.data_mut().as_mut_ptr() as usize;
+        self.hover = self.hover_range();
+        self.draw_hover();
+        self.draw_marks();
 
-        let stride = width * 4;
+        self.draw_cursor(true);
 
-        let offset = y * stride + start_x * 4;
-        offscreen_ptr += offset;
+        self.last_selection = self.selection;
 
-        let mut rows = end_y - start_y;
-        while rows > 0 {
-            let mut row_ptr = offscreen_ptr;
-            let mut cols = len;
-            while cols > 0 {
-                unsafe {
-                    let color = *(row_ptr as *mut u32);
-                    *(row_ptr as *mut u32) = color ^ 0x00FFFFFF;
-                }
-                row_ptr += 4;
-                cols -= 1;
-            }
-            offscreen_ptr += stride;
-            rows -= 1;
+        if sync {
+            self.sync();
         }
+
+        Ok(buf.len())
     }
 
-    pub fn write(&mut self, buf: &[u8], sync: bool) -> Result<usize> {
+    fn write_ransid(&mut self, buf: &[u8]) {
         let alpha = self.alpha;
-        let cvt = |color: ransid::Color| -> Color {
+        let palette = &self.palette;
+        let cvt = |rgb: u32| -> Color {
             Color {
-                data: ((alpha as u32) << 24) | (color.as_rgb() & 0xFFFFFF),
+                data: ((alpha as u32) << 24) | rgb,
             }
         };
 
-        if let Some(selection) = self.last_selection {
-            for i in cmp::min(selection.0, selection.1)..cmp::max(selection.0, selection.1) {
-                let x = i % self.ransid.state.w;
-                let y = i / self.ransid.state.w;
-                let block_width = self.block_width;
-                let block_height = self.block_height;
-                self.invert(x * block_width, y * block_height, block_width, block_height);
-                self.changed.insert(y as usize);
-            }
-        }
-
-        if self.ransid.state.cursor
-            && self.ransid.state.x < self.ransid.state.w
-            && self.ransid.state.y < self.ransid.state.h
-        {
-            let x = self.ransid.state.x;
-            let y = self.ransid.state.y;
-            let block_width = self.block_width;
-            let block_height = self.block_height;
-            self.invert(x * block_width, y * block_height, block_width, block_height);
-            self.changed.insert(y);
-        }
+        let font = &self.font;
+        let font_bold = &self.font_bold;
+        let console_bg = self.ransid.state.background;
+        let console_w = self.ransid.state.w;
+        let console_h = self.ransid.state.h;
+        let block_width = self.block_width;
+        let block_height = self.block_height;
+        let alt = &mut self.alternate;
+        let grid = &mut self.grid;
+        let alt_grid = &mut self.alt_grid;
+        let window = &mut self.window;
+        let input = &mut self.input;
+        let changed = &mut self.changed;
+        let scrolled = &mut self.scrolled;
+        let alt_scrolled = &mut self.alt_scrolled;
+        let last_char = &mut self.last_char;
+        let scrolled_output = &mut self.scrolled_output;
+        let output_open = &mut self.output_open;
+        let link = self.link;
+        let mut str_buf = [0; 4];
+        self.ransid.write(buf, |event| {
+            match event {
+                ransid::Event::Char {
+                    x,
+                    y,
+                    c,
+                    color,
+                    bold,
+                    ..
+                } => {
+                    if bold {
+                        font_bold
+                            .render(&c.encode_utf8(&mut str_buf), block_height as f32)
+                            .draw(
+                                window,
+                                x as i32 * block_width as i32,
+                                y as i32 * block_height as i32,
+                                cvt(palette.fg(color)),
+                            );
+                    } else {
+                        font.render(&c.encode_utf8(&mut str_buf), block_height as f32)
+                            .draw(
+                                window,
+                                x as i32 * block_width as i32,
+                                y as i32 * block_height as i32,
+                                cvt(palette.fg(color)),
+                            );
+                    }
 
-        {
-            let font = &self.font;
-            let font_bold = &self.font_bold;
-            let console_bg = self.ransid.state.background;
-            let console_w = self.ransid.state.w;
-            let console_h = self.ransid.state.h;
-            let block_width = self.block_width;
-            let block_height = self.block_height;
-            let alt = &mut self.alternate;
-            let grid = &mut self.grid;
-            let alt_grid = &mut self.alt_grid;
-            let window = &mut self.window;
-            let input = &mut self.input;
-            let changed = &mut self.changed;
-            let mut str_buf = [0; 4];
-            self.ransid.write(buf, |event| {
-                match event {
-                    ransid::Event::Char {
-                        x,
-                        y,
-                        c,
-                        color,
-                        bold,
-                        ..
-                    } => {
-                        if bold {
-                            font_bold
-                                .render(&c.encode_utf8(&mut str_buf), block_height as f32)
-                                .draw(
-                                    window,
-                                    x as i32 * block_width as i32,
-                                    y as i32 * block_height as i32,
-                                    cvt(color),
-                                );
-                        } else {
-                            font.render(&c.encode_utf8(&mut str_buf), block_height as f32)
-                                .draw(
-                                    window,
-                                    x as i32 * block_width as i32,
-                                    y as i32 * block_height as i32,
-                                    cvt(color),
-                                );
-                        }
+                    if let Some(ref mut block) = grid.get_mut(y * console_w + x) {
+                        block.c = c;
+                        block.fg = cvt(palette.fg(color));
+                        block.raw_fg = color;
+                        block.bold = bold;
+                        block.link = link;
+                    }
 
-                        if let Some(ref mut block) = grid.get_mut(y * console_w + x) {
-                            block.c = c;
-                            block.fg = cvt(color);
-                            block.bold = bold;
+                    // Printing past the end of a row continues on the next one
+                    if let Some((last_x, last_y)) = *last_char {
+                        if x == 0 && last_x + 1 == console_w && last_y + 1 == y {
+                            if let Some(ref mut block) = grid.get_mut(last_y * console_w + last_x) {
+                                block.wrapped = true;
+                            }
                         }
-
-                        changed.insert(y);
-                    }
-                    ransid::Event::Input { data } => {
-                        input.extend(data);
                     }
-                    ransid::Event::Rect { x, y, w, h, color } => {
-                        window.mode().set(Mode::Overwrite);
-                        window.rect(
-                            x as i32 * block_width as i32,
-                            y as i32 * block_height as i32,
-                            w as u32 * block_width as u32,
-                            h as u32 * block_height as u32,
-                            cvt(color),
-                        );
-                        window.mode().set(Mode::Blend);
+                    *last_char = Some((x, y));
 
-                        for y2 in y..y + h {
-                            for x2 in x..x + w {
-                                if let Some(ref mut block) = grid.get_mut(y2 * console_w + x2) {
-                                    block.c = '\0';
-                                    block.bg = cvt(color);
+                    changed.insert(y);
+                }
+                ransid::Event::Input { data } => {
+                    input.extend(data);
+                }
+                ransid::Event::Rect { x, y, w, h, color } => {
+                    window.mode().set(Mode::Overwrite);
+                    window.rect(
+                        x as i32 * block_width as i32,
+                        y as i32 * block_height as i32,
+                        w as u32 * block_width as u32,
+                        h as u32 * block_height as u32,
+                        cvt(palette.bg(color)),
+                    );
+                    window.mode().set(Mode::Blend);
+
+                    for y2 in y..y + h {
+                        for x2 in x..x + w {
+                            if let Some(ref mut block) = grid.get_mut(y2 * console_w + x2) {
+                                block.c = '\0';
+                                block.bg = cvt(palette.bg(color));
+                                block.raw_bg = color;
+                                block.wrapped = false;
+                                block.link = 0;
+                                // Only erasing whole rows removes marks, printing also erases blocks
+                                if w == console_w {
+                                    block.mark = None;
                                 }
                             }
-                            changed.insert(y2);
                         }
+                        changed.insert(y2);
                     }
-                    ransid::Event::ScreenBuffer { alternate, clear } => {
-                        if *alt != alternate {
-                            mem::swap(grid, alt_grid);
+                }
+                ransid::Event::ScreenBuffer { alternate, clear } => {
+                    if *alt != alternate {
+                        mem::swap(grid, alt_grid);
+                        mem::swap(scrolled, alt_scrolled);
 
-                            window.set(cvt(console_bg));
+                        window.set(cvt(palette.bg(console_bg)));
 
-                            for y in 0..console_h {
-                                for x in 0..console_w {
-                                    let block = &mut grid[y * console_w + x];
+                        for y in 0..console_h {
+                            for x in 0..console_w {
+                                let block = &mut grid[y * console_w + x];
 
-                                    if clear {
-                                        block.c = '\0';
-                                        block.bg = cvt(console_bg);
-                                    }
+                                if clear {
+                                    block.c = '\0';
+                                    block.bg = cvt(palette.bg(console_bg));
+                                    block.raw_bg = console_bg;
+                                    block.wrapped = false;
+                                    block.link = 0;
+                                    block.mark = None;
+                                }
 
-                                    window.mode().set(Mode::Overwrite);
-                                    window.rect(
-                                        x as i32 * block_width as i32,
-                                        y as i32 * block_height as i32,
-                                        block_width as u32,
-                                        block_height as u32,
-                                        block.bg,
-                                    );
-                                    window.mode().set(Mode::Blend);
-
-                                    if block.c != '\0' {
-                                        if block.bold {
-                                            font_bold
-                                                .render(
-                                                    &block.c.encode_utf8(&mut str_buf),
-                                                    block_height as f32,
-                                                )
-                                                .draw(
-                                                    window,
-                                                    x as i32 * block_width as i32,
-                                                    y as i32 * block_height as i32,
-                                                    block.fg,
-                                                );
-                                        } else {
-                                            font.render(
+                                window.mode().set(Mode::Overwrite);
+                                window.rect(
+                                    x as i32 * block_width as i32,
+                                    y as i32 * block_height as i32,
+                                    block_width as u32,
+                                    block_height as u32,
+                                    block.bg,
+                                );
+                                window.mode().set(Mode::Blend);
+
+                                if block.c != '\0' {
+                                    if block.bold {
+                                        font_bold
+                                            .render(
                                                 &block.c.encode_utf8(&mut str_buf),
                                                 block_height as f32,
                                             )
@@ -576,59 +1993,78 @@ impl Console {
                                                 y as i32 * block_height as i32,
                                                 block.fg,
                                             );
-                                        }
+                                    } else {
+                                        font.render(
+                                            &block.c.encode_utf8(&mut str_buf),
+                                            block_height as f32,
+                                        )
+                                        .draw(
+                                            window,
+                                            x as i32 * block_width as i32,
+                                            y as i32 * block_height as i32,
+                                            block.fg,
+                                        );
                                     }
                                 }
-                                changed.insert(y as usize);
                             }
+                            changed.insert(y as usize);
                         }
-                        *alt = alternate;
                     }
-                    ransid::Event::Move {
-                        from_x,
-                        from_y,
-                        to_x,
-                        to_y,
-                        w,
-                        h,
-                    } => {
-                        let width = window.width() as usize;
-                        let pixels = window.data_mut();
-
-                        for raw_y in 0..h {
-                            let y = if from_y > to_y { raw_y } else { h - raw_y - 1 };
-
-                            for pixel_y in 0..block_height {
-                                {
-                                    let off_from = ((from_y + y) * block_height + pixel_y) * width
-                                        + from_x * block_width;
-                                    let off_to = ((to_y + y) * block_height + pixel_y) * width
-                                        + to_x * block_width;
-                                    let len = w * block_width;
-
-                                    if off_from + len <= pixels.len()
-                                        && off_to + len <= pixels.len()
-                                    {
-                                        unsafe {
-                                            let data_ptr = pixels.as_mut_ptr() as *mut u32;
-                                            ptr::copy(
-                                                data_ptr.offset(off_from as isize),
-                                                data_ptr.offset(off_to as isize),
-                                                len,
-                                            );
-                                        }
-                                    }
-                                }
+                    *alt = alternate;
+                }
+                ransid::Event::Move {
+                    from_x,
+                    from_y,
+                    to_x,
+                    to_y,
+                    w,
+                    h,
+                } => {
+                    // Scrolling the whole screen moves lines off or onto it, unlike scrolling a
+                    // region or inserting and deleting lines
+                    let distance = cmp::max(from_y, to_y) - cmp::min(from_y, to_y);
+                    if from_x == 0
+                        && to_x == 0
+                        && w == console_w
+                        && cmp::min(from_y, to_y) == 0
+                        && h + distance == console_h
+                    {
+                        *scrolled += from_y as isize - to_y as isize;
+                        if !*alt && to_y == 0 {
+                            for y in 0..cmp::min(from_y, console_h) {
+                                let row = &grid[y * console_w..(y + 1) * console_w];
+                                keep_output(row, scrolled_output, output_open);
                             }
+                        }
+                    }
+                    if let Some((last_x, last_y)) = *last_char {
+                        if from_x == 0
+                            && to_x == 0
+                            && w == console_w
+                            && last_y >= from_y
+                            && last_y < from_y + h
+                        {
+                            *last_char = Some((last_x, last_y + to_y - from_y));
+                        }
+                    }
+
+                    let width = window.width() as usize;
+                    let pixels = window.data_mut();
+
+                    for raw_y in 0..h {
+                        let y = if from_y > to_y { raw_y } else { h - raw_y - 1 };
 
+                        for pixel_y in 0..block_height {
                             {
-                                let off_from = (from_y + y) * console_w + from_x;
-                                let off_to = (to_y + y) * console_w + to_x;
-                                let len = w;
+                                let off_from = ((from_y + y) * block_height + pixel_y) * width
+                                    + from_x * block_width;
+                                let off_to = ((to_y + y) * block_height + pixel_y) * width
+                                    + to_x * block_width;
+                                let len = w * block_width;
 
-                                if off_from + len <= grid.len() && off_to + len <= grid.len() {
+                                if off_from + len <= pixels.len() && off_to + len <= pixels.len() {
                                     unsafe {
-                                        let data_ptr = grid.as_mut_ptr();
+                                        let data_ptr = pixels.as_mut_ptr() as *mut u32;
                                         ptr::copy(
                                             data_ptr.offset(off_from as isize),
                                             data_ptr.offset(off_to as isize),
@@ -637,61 +2073,178 @@ impl Console {
                                     }
                                 }
                             }
+                        }
 
-                            changed.insert(to_y + y);
+                        {
+                            let off_from = (from_y + y) * console_w + from_x;
+                            let off_to = (to_y + y) * console_w + to_x;
+                            let len = w;
+
+                            if off_from + len <= grid.len() && off_to + len <= grid.len() {
+                                unsafe {
+                                    let data_ptr = grid.as_mut_ptr();
+                                    ptr::copy(
+                                        data_ptr.offset(off_from as isize),
+                                        data_ptr.offset(off_to as isize),
+                                        len,
+                                    );
+                                }
+                            }
                         }
+
+                        changed.insert(to_y + y);
                     }
-                    ransid::Event::Resize { w, h } => {
-                        //TODO: Make sure grid is resized
-                        window.set_size(
-                            w as u32 * block_width as u32,
-                            h as u32 * block_height as u32,
-                        );
-                    }
-                    ransid::Event::Title { title } => {
-                        window.set_title(&title);
+                }
+                ransid::Event::Resize { w, h } => {
+                    //TODO: Make sure grid is resized
+                    window.set_size(
+                        w as u32 * block_width as u32,
+                        h as u32 * block_height as u32,
+                    );
+                }
+                ransid::Event::Title { .. } => {
+                    // The parser handles titles, ransid takes any OSC starting with 0, 1 or 2 for one
+                }
+            }
+        });
+    }
+
+    fn notify(&mut self, title: Option<String>, body: String) {
+        if self.config.notifications.show {
+            // Long lines are cut to fit the window
+            let columns = self.ransid.state.w.saturating_sub(6);
+            let lines = title
+                .iter()
+                .chain(Some(&body))
+                .flat_map(|text| text.lines())
+                .filter(|line| !line.is_empty())
+                .map(|line| line.chars().take(columns).collect())
+                .collect::<Vec<String>>();
+            // Remove the previous notification, the cursor and selection are drawn after writing
+            if let Some((previous, _)) = self.toast.take() {
+                let (_columns, rows) = Console::box_size(&previous);
+                for y in 1..cmp::min(1 + rows, self.ransid.state.h) {
+                    for x in 0..self.ransid.state.w {
+                        self.draw_block(x, y, None);
                     }
+                    self.changed.insert(y);
                 }
-            });
+            }
+            if !lines.is_empty() {
+                self.toast = Some((lines, Instant::now() + TOAST_TIME));
+            }
         }
 
-        if self.ransid.state.cursor
-            && self.ransid.state.x < self.ransid.state.w
-            && self.ransid.state.y < self.ransid.state.h
-        {
-            let x = self.ransid.state.x;
-            let y = self.ransid.state.y;
-            let block_width = self.block_width;
-            let block_height = self.block_height;
-            self.invert(x * block_width, y * block_height, block_width, block_height);
-            self.changed.insert(y as usize);
+        if !self.focused && !self.attention {
+            self.attention = true;
+            self.update_title();
         }
 
-        if let Some(selection) = self.selection {
-            for i in cmp::min(selection.0, selection.1)..cmp::max(selection.0, selection.1) {
-                let x = i % self.ransid.state.w;
-                let y = i / self.ransid.state.w;
-                let block_width = self.block_width;
-                let block_height = self.block_height;
-                self.invert(x * block_width, y * block_height, block_width, block_height);
-                self.changed.insert(y as usize);
+        // A stream of notifications must not start a process for each one, so they are dropped
+        // while the command runs and shortly after it started
+        let busy = match self.notifier {
+            Some((ref mut child, started)) => {
+                started.elapsed() < NOTIFY_INTERVAL || matches!(child.try_wait(), Ok(None))
+            }
+            None => false,
+        };
+        let mut args = self.config.notifications.command.split_whitespace();
+        match args.next() {
+            Some(program) if !busy => {
+                let title = title.unwrap_or_else(|| self.title.clone());
+                match process::Command::new(program)
+                    .args(args)
+                    .arg(title)
+                    .arg(body)
+                    .spawn()
+                {
+                    Ok(child) => self.notifier = Some((child, Instant::now())),
+                    Err(err) => eprintln!("orbterm: failed to run {:?}: {}", program, err),
+                }
             }
+            _ => (),
         }
+    }
 
-        self.last_selection = self.selection;
+    /// Hide the notification once its time is up and reap the notification command
+    pub fn tick(&mut self) {
+        let finished = match self.notifier {
+            Some((ref mut child, started)) => {
+                let running = matches!(child.try_wait(), Ok(None));
+                !running && started.elapsed() >= NOTIFY_INTERVAL
+            }
+            None => false,
+        };
+        if finished {
+            self.notifier = None;
+        }
 
-        if sync {
+        let expired = match self.toast {
+            Some((_, until)) => Instant::now() >= until,
+            None => false,
+        };
+        if expired {
+            self.toast = None;
+            self.repaint();
             self.sync();
         }
+    }
 
-        Ok(buf.len())
+    fn update_title(&mut self) {
+        // Shorten the home directory to ~, like shells do
+        let cwd = match self.cwd {
+            Some(ref cwd) => match env::var("HOME") {
+                Ok(ref home)
+                    if !home.is_empty()
+                        && cwd.starts_with(home.as_str())
+                        && (cwd.len() == home.len() || cwd[home.len()..].starts_with('/')) =>
+                {
+                    format!("~{}", &cwd[home.len()..])
+                }
+                _ => cwd.clone(),
+            },
+            None => String::new(),
+        };
+        // The leader of the foreground process group is named after the running program
+        let process = if self.foreground > 0 {
+            fs::read_to_string(format!("/proc/{}/comm", self.foreground))
+                .map(|name| name.trim_end().to_string())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let process = if process.is_empty() {
+            &self.process
+        } else {
+            &process
+        };
+        let title = template(
+            &self.config.title.template,
+            &[
+                ("title", &self.title),
+                ("icon", &self.icon),
+                ("cwd", &cwd),
+                ("process", process),
+            ],
+        );
+        let title = match self.progress {
+            Some(Progress::Normal(percent))
+            | Some(Progress::Error(percent))
+            | Some(Progress::Paused(percent)) => format!("[{}%] {}", percent, title),
+            _ => title,
+        };
+        if self.attention {
+            self.window.set_title(&format!("(!) {}", title));
+        } else {
+            self.window.set_title(&title);
+        }
     }
 
     fn resize_grid(&mut self, w: usize, h: usize) {
         let alpha = self.alpha;
-        let cvt = |color: ransid::Color| -> Color {
+        let cvt = |rgb: u32| -> Color {
             Color {
-                data: ((alpha as u32) << 24) | (color.as_rgb() & 0xFFFFFF),
+                data: ((alpha as u32) << 24) | rgb,
             }
         };
 
@@ -699,9 +2252,14 @@ impl Console {
             let mut grid = vec![
                 Block {
                     c: '\0',
-                    fg: cvt(self.ransid.state.foreground),
-                    bg: cvt(self.ransid.state.background),
-                    bold: false
+                    fg: cvt(self.palette.fg(self.ransid.state.foreground)),
+                    bg: cvt(self.palette.bg(self.ransid.state.background)),
+                    bold: false,
+                    raw_fg: self.ransid.state.foreground,
+                    raw_bg: self.ransid.state.background,
+                    wrapped: false,
+                    link: 0,
+                    mark: None
                 };
                 w * h
             ]
@@ -710,15 +2268,21 @@ impl Console {
             let mut alt_grid = vec![
                 Block {
                     c: '\0',
-                    fg: cvt(self.ransid.state.foreground),
-                    bg: cvt(self.ransid.state.background),
-                    bold: false
+                    fg: cvt(self.palette.fg(self.ransid.state.foreground)),
+                    bg: cvt(self.palette.bg(self.ransid.state.background)),
+                    bold: false,
+                    raw_fg: self.ransid.state.foreground,
+                    raw_bg: self.ransid.state.background,
+                    wrapped: false,
+                    link: 0,
+                    mark: None
                 };
                 w * h
             ]
             .into_boxed_slice();
 
-            self.window.set(cvt(self.ransid.state.background));
+            self.window
+                .set(cvt(self.palette.bg(self.ransid.state.background)));
 
             {
                 let font = &self.font;
@@ -780,19 +2344,17 @@ impl Console {
             self.grid = grid;
             self.alt_grid = alt_grid;
 
-            if self.ransid.state.cursor
-                && self.ransid.state.x < self.ransid.state.w
-                && self.ransid.state.y < self.ransid.state.h
-            {
-                let x = self.ransid.state.x;
-                let y = self.ransid.state.y;
-                let block_width = self.block_width;
-                let block_height = self.block_height;
-                self.invert(x * block_width, y * block_height, block_width, block_height);
+            // Everything was drawn again, so there is no underline to remove
+            self.hover = None;
+
+            // Selections are anchored to lines, so they only need to be drawn again
+            if let Some(selection) = self.selection {
+                self.draw_selection(selection, true);
             }
+            self.last_selection = self.selection;
 
-            //TODO: Figure out what should happen on resize
-            self.selection = None;
+            self.draw_marks();
+            self.draw_cursor(true);
         }
     }
 
@@ -807,7 +2369,198 @@ impl Console {
         self.block_height = self.block_width * 2;
     }
 
+    /// Fit the grid to the window after the block size changed
+    fn apply_zoom(&mut self) {
+        let w = self.window.width() as usize / self.block_width;
+        let h = self.window.height() as usize / self.block_height;
+
+        self.resize_grid(w, h);
+        self.sync();
+    }
+
+    /// Draw a block with its own colors, or the given foreground and background
+    fn draw_block(&mut self, x: usize, y: usize, colors: Option<(Color, Color)>) {
+        let mut block = match self.grid.get(y * self.ransid.state.w + x) {
+            Some(block) => *block,
+            None => return,
+        };
+        if let Some((fg, bg)) = colors {
+            block.fg = fg;
+            block.bg = bg;
+        }
+
+        self.window.mode().set(Mode::Overwrite);
+        self.window.rect(
+            x as i32 * self.block_width as i32,
+            y as i32 * self.block_height as i32,
+            self.block_width as u32,
+            self.block_height as u32,
+            block.bg,
+        );
+        self.window.mode().set(Mode::Blend);
+
+        if block.c != '\0' {
+            let font = if block.bold {
+                &self.font_bold
+            } else {
+                &self.font
+            };
+            let mut str_buf = [0; 4];
+            font.render(block.c.encode_utf8(&mut str_buf), self.block_height as f32)
+                .draw(
+                    &mut self.window,
+                    x as i32 * self.block_width as i32,
+                    y as i32 * self.block_height as i32,
+                    block.fg,
+                );
+        }
+    }
+
+    /// Redraw the grid from scratch, removing any overlay
+    fn repaint(&mut self) {
+        for y in 0..self.ransid.state.h {
+            for x in 0..self.ransid.state.w {
+                self.draw_block(x, y, None);
+            }
+            self.changed.insert(y);
+        }
+
+        if let Some(selection) = self.last_selection {
+            self.draw_selection(selection, true);
+        }
+
+        self.draw_hover();
+        self.draw_marks();
+
+        self.draw_cursor(true);
+    }
+
+    /// Underline the link under the mouse
+    fn draw_hover(&mut self) {
+        let hover = match self.hover {
+            Some(ref hover) => hover.clone(),
+            None => return,
+        };
+        let thickness = cmp::max(1, self.block_height / 16);
+        for i in hover {
+            let block = match self.grid.get(i) {
+                Some(block) => *block,
+                None => continue,
+            };
+            let x = i % self.ransid.state.w;
+            let y = i / self.ransid.state.w;
+            self.window.rect(
+                (x * self.block_width) as i32,
+                ((y + 1) * self.block_height - thickness) as i32,
+                self.block_width as u32,
+                thickness as u32,
+                block.fg,
+            );
+            self.changed.insert(y);
+        }
+    }
+
+    /// Draw lines of text in a box centered on the grid
+    fn draw_overlay(&mut self, lines: &[String]) {
+        let (columns, rows) = Console::box_size(lines);
+        let x = self.ransid.state.w.saturating_sub(columns) / 2;
+        let y = self.ransid.state.h.saturating_sub(rows) / 2;
+        self.draw_box(lines, x, y);
+    }
+
+    /// Draw the progress bar along the top edge of the window
+    fn draw_progress(&mut self) {
+        let (percent, color) = match self.progress {
+            Some(Progress::Normal(percent)) => (percent, self.palette.colors[2]),
+            Some(Progress::Error(percent)) => (percent, self.palette.colors[1]),
+            Some(Progress::Indeterminate) => (100, self.palette.colors[4]),
+            Some(Progress::Paused(percent)) => (percent, self.palette.colors[3]),
+            None => return,
+        };
+        let width = self.ransid.state.w * self.block_width * percent as usize / 100;
+        let thickness = cmp::max(2, self.block_height / 8);
+
+        self.window.mode().set(Mode::Overwrite);
+        self.window.rect(
+            0,
+            0,
+            width as u32,
+            thickness as u32,
+            Color {
+                data: 0xFF000000 | color,
+            },
+        );
+        self.window.mode().set(Mode::Blend);
+        self.changed.insert(0);
+    }
+
+    /// Draw the notification in the top right corner
+    fn draw_toast(&mut self) {
+        let lines = match self.toast {
+            Some((ref lines, _)) => lines.clone(),
+            None => return,
+        };
+        let (columns, _rows) = Console::box_size(&lines);
+        let x = self.ransid.state.w.saturating_sub(columns + 1);
+        self.draw_box(&lines, x, 1);
+    }
+
+    fn box_size(lines: &[String]) -> (usize, usize) {
+        let columns = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 4;
+        (columns, lines.len() + 2)
+    }
+
+    fn draw_box(&mut self, lines: &[String], x: usize, y: usize) {
+        let (columns, rows) = Console::box_size(lines);
+
+        self.window.mode().set(Mode::Overwrite);
+        self.window.rect(
+            (x * self.block_width) as i32,
+            (y * self.block_height) as i32,
+            (columns * self.block_width) as u32,
+            (rows * self.block_height) as u32,
+            Color::rgba(0x20, 0x20, 0x20, 0xF0),
+        );
+        self.window.mode().set(Mode::Blend);
+
+        for (i, line) in lines.iter().enumerate() {
+            self.font.render(line, self.block_height as f32).draw(
+                &mut self.window,
+                ((x + 2) * self.block_width) as i32,
+                ((y + 1 + i) * self.block_height) as i32,
+                Color::rgb(0xFF, 0xFF, 0xFF),
+            );
+        }
+
+        for row in y..y + rows {
+            self.changed.insert(row);
+        }
+    }
+
     fn sync(&mut self) {
+        let prompt = self
+            .pending_paste
+            .as_ref()
+            .map(|pending_paste| pending_paste.prompt());
+        if let Some(prompt) = prompt {
+            self.draw_overlay(&prompt);
+        }
+        let prompt = self
+            .copy_mode
+            .as_ref()
+            .and_then(|copy_mode| copy_mode.prompt());
+        if let Some(prompt) = prompt {
+            self.draw_overlay(&prompt);
+        }
+        self.draw_hints();
+        self.draw_progress();
+        self.draw_toast();
+
         /*
         let width = self.window.width;
         for change in self.changed.iter() {
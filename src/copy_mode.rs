@@ -0,0 +1,322 @@
+// Keyboard driven selection with vi style keys
+
+use std::cmp;
+
+use console::{Block, Mark};
+use orbclient;
+use selection::{Point, Selection};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visual {
+    Char,
+    Line,
+    Block,
+}
+
+/// What the console should do after a key in copy mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    None,
+    /// Copy the selection and leave copy mode
+    Yank,
+    Exit,
+}
+
+/// The grid as seen by copy mode
+pub struct Screen<'a> {
+    pub grid: &'a [Block],
+    pub w: usize,
+    pub h: usize,
+    /// Line shown in the first row of the grid
+    pub top: isize,
+    pub word_separators: &'a str,
+}
+
+impl<'a> Screen<'a> {
+    fn len(&self) -> usize {
+        self.w * self.h
+    }
+
+    fn index(&self, point: Point) -> usize {
+        (point.line - self.top) as usize * self.w + point.column
+    }
+
+    fn point(&self, i: usize) -> Point {
+        Point {
+            line: self.top + (i / self.w) as isize,
+            column: i % self.w,
+        }
+    }
+
+    fn clamp(&self, point: Point) -> Point {
+        Point {
+            line: cmp::max(
+                self.top,
+                cmp::min(self.top + self.h as isize - 1, point.line),
+            ),
+            column: cmp::min(self.w - 1, point.column),
+        }
+    }
+
+    fn char_at(&self, i: usize) -> char {
+        match self.grid.get(i) {
+            Some(block) if block.c != '\0' => block.c,
+            _ => ' ',
+        }
+    }
+
+    fn is_prompt(&self, i: usize) -> bool {
+        match self.grid.get(i) {
+            Some(block) => matches!(block.mark, Some(Mark::Prompt { .. })),
+            None => false,
+        }
+    }
+
+    /// Blanks, separators and other characters each form their own words
+    fn class(&self, i: usize) -> u8 {
+        let c = self.char_at(i);
+        if c.is_whitespace() {
+            0
+        } else if self.word_separators.contains(c) {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+pub struct CopyMode {
+    pub cursor: Point,
+    anchor: Point,
+    visual: Option<Visual>,
+    /// Waiting for the second `g` of `gg`
+    g: bool,
+    /// Search being typed after `/`
+    pub search: Option<String>,
+    last_search: String,
+}
+
+impl CopyMode {
+    pub fn new(cursor: Point) -> CopyMode {
+        CopyMode {
+            cursor,
+            anchor: cursor,
+            visual: None,
+            g: false,
+            search: None,
+            last_search: String::new(),
+        }
+    }
+
+    /// The visual selection, or the cursor when there is none
+    pub fn selection(&self, w: usize) -> Selection {
+        let start = cmp::min(self.anchor, self.cursor);
+        let end = cmp::max(self.anchor, self.cursor);
+        match self.visual {
+            Some(Visual::Char) => Selection::new(
+                start,
+                Point {
+                    column: end.column + 1,
+                    ..end
+                },
+            ),
+            Some(Visual::Line) => Selection::new(
+                Point {
+                    line: start.line,
+                    column: 0,
+                },
+                Point {
+                    line: end.line,
+                    column: w,
+                },
+            ),
+            Some(Visual::Block) => Selection {
+                start: Point {
+                    line: start.line,
+                    column: cmp::min(self.anchor.column, self.cursor.column),
+                },
+                end: Point {
+                    line: end.line,
+                    column: cmp::max(self.anchor.column, self.cursor.column),
+                },
+                block: true,
+            },
+            None => Selection::new(
+                self.cursor,
+                Point {
+                    column: self.cursor.column + 1,
+                    ..self.cursor
+                },
+            ),
+        }
+    }
+
+    fn toggle_visual(&mut self, visual: Visual) {
+        if self.visual == Some(visual) {
+            self.visual = None;
+        } else {
+            if self.visual.is_none() {
+                self.anchor = self.cursor;
+            }
+            self.visual = Some(visual);
+        }
+    }
+
+    /// Move the cursor to the next or previous prompt on the screen, there is no scrollback to
+    /// move into
+    pub fn find_prompt(&mut self, forward: bool, screen: &Screen) {
+        let i = screen.index(screen.clamp(self.cursor));
+        let prompt = if forward {
+            (i + 1..screen.len()).find(|&i| screen.is_prompt(i))
+        } else {
+            (0..i).rev().find(|&i| screen.is_prompt(i))
+        };
+        if let Some(prompt) = prompt {
+            self.cursor = screen.point(prompt);
+        }
+    }
+
+    /// Move the cursor to the next match of the last search
+    fn find(&mut self, forward: bool, screen: &Screen) {
+        let needle: Vec<char> = self.last_search.chars().collect();
+        let len = screen.len();
+        if needle.is_empty() || needle.len() > len {
+            return;
+        }
+
+        let matches = |i: usize| {
+            needle
+                .iter()
+                .enumerate()
+                .all(|(j, &c)| screen.char_at(i + j) == c)
+        };
+        let start = screen.index(screen.clamp(self.cursor));
+        let count = len - needle.len() + 1;
+        for step in 1..=count {
+            let i = if forward {
+                (start + step) % count
+            } else {
+                (start + count * 2 - step) % count
+            };
+            if matches(i) {
+                self.cursor = screen.point(i);
+                return;
+            }
+        }
+    }
+
+    /// Handle a typed character
+    pub fn text(&mut self, c: char, ctrl: bool, screen: &Screen) -> Command {
+        if let Some(ref mut search) = self.search {
+            if !c.is_control() {
+                search.push(c);
+            }
+            return Command::None;
+        }
+
+        self.cursor = screen.clamp(self.cursor);
+        let g = self.g;
+        self.g = false;
+
+        let i = screen.index(self.cursor);
+        let row = i - i % screen.w;
+        match c {
+            'v' if ctrl => self.toggle_visual(Visual::Block),
+            '\x16' => self.toggle_visual(Visual::Block),
+            'v' => self.toggle_visual(Visual::Char),
+            'V' => self.toggle_visual(Visual::Line),
+            'h' => self.cursor.column = self.cursor.column.saturating_sub(1),
+            'l' => self.cursor.column += 1,
+            'j' => self.cursor.line += 1,
+            'k' => self.cursor.line -= 1,
+            '0' => self.cursor.column = 0,
+            '$' => {
+                let end = (row..row + screen.w)
+                    .rposition(|i| screen.char_at(i) != ' ')
+                    .unwrap_or(0);
+                self.cursor.column = end;
+            }
+            'w' => {
+                let mut i = i;
+                let class = screen.class(i);
+                if class != 0 {
+                    while i < screen.len() && screen.class(i) == class {
+                        i += 1;
+                    }
+                }
+                while i < screen.len() && screen.class(i) == 0 {
+                    i += 1;
+                }
+                if i < screen.len() {
+                    self.cursor = screen.point(i);
+                }
+            }
+            'b' => {
+                let mut i = i;
+                if i > 0 {
+                    i -= 1;
+                    while i > 0 && screen.class(i) == 0 {
+                        i -= 1;
+                    }
+                    let class = screen.class(i);
+                    while i > 0 && screen.class(i - 1) == class {
+                        i -= 1;
+                    }
+                    self.cursor = screen.point(i);
+                }
+            }
+            'g' if g => self.cursor = screen.point(0),
+            'g' => self.g = true,
+            'G' => self.cursor = screen.point(screen.len() - screen.w),
+            '/' => self.search = Some(String::new()),
+            'n' => self.find(true, screen),
+            'N' => self.find(false, screen),
+            '[' => self.find_prompt(false, screen),
+            ']' => self.find_prompt(true, screen),
+            'y' if self.visual.is_some() => return Command::Yank,
+            'q' => return Command::Exit,
+            _ => (),
+        }
+
+        self.cursor = screen.clamp(self.cursor);
+        Command::None
+    }
+
+    /// Handle a key press that does not produce text
+    pub fn key(&mut self, scancode: u8, screen: &Screen) -> Command {
+        if let Some(mut search) = self.search.take() {
+            match scancode {
+                orbclient::K_ENTER => {
+                    self.last_search = search;
+                    self.find(true, screen);
+                }
+                orbclient::K_BKSP => {
+                    search.pop();
+                    self.search = Some(search);
+                }
+                orbclient::K_ESC => (),
+                _ => self.search = Some(search),
+            }
+            return Command::None;
+        }
+
+        match scancode {
+            orbclient::K_ESC if self.visual.is_some() => self.visual = None,
+            orbclient::K_ESC => return Command::Exit,
+            orbclient::K_ENTER if self.visual.is_some() => return Command::Yank,
+            orbclient::K_LEFT => return self.text('h', false, screen),
+            orbclient::K_DOWN => return self.text('j', false, screen),
+            orbclient::K_UP => return self.text('k', false, screen),
+            orbclient::K_RIGHT => return self.text('l', false, screen),
+            _ => (),
+        }
+        Command::None
+    }
+
+    /// Text of the overlay shown while typing a search
+    pub fn prompt(&self) -> Option<Vec<String>> {
+        self.search
+            .as_ref()
+            .map(|search| vec![format!("/{}", search)])
+    }
+}
@@ -0,0 +1,130 @@
+// Conversion of selected blocks to text, with or without their colors and attributes
+
+extern crate ransid;
+
+use console::Block;
+
+fn text(block: &Block) -> char {
+    if block.c == '\0' {
+        ' '
+    } else {
+        block.c
+    }
+}
+
+/// Foreground, background and weight of a block. The default colors are left out, so the text
+/// takes the colors of wherever it is pasted.
+fn style(block: &Block) -> (Option<u32>, Option<u32>, bool) {
+    let fg = match block.raw_fg {
+        ransid::Color::Ansi(7) => None,
+        _ => Some(block.fg.data & 0xFFFFFF),
+    };
+    let bg = match block.raw_bg {
+        ransid::Color::Ansi(0) => None,
+        _ => Some(block.bg.data & 0xFFFFFF),
+    };
+    (fg, bg, block.bold)
+}
+
+/// Split a row into runs of blocks with the same style
+fn runs(row: &[Block]) -> Vec<&[Block]> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for i in 1..=row.len() {
+        if i == row.len() || style(&row[i]) != style(&row[start]) {
+            runs.push(&row[start..i]);
+            start = i;
+        }
+    }
+    runs
+}
+
+/// Plain text. Each row is given with a flag that is set when it continues on the next row.
+pub fn plain(rows: &[(Vec<Block>, bool)]) -> String {
+    let mut string = String::new();
+    for (n, (row, wrapped)) in rows.iter().enumerate() {
+        string.extend(row.iter().map(text));
+        if !wrapped && n + 1 < rows.len() {
+            string.push('\n');
+        }
+    }
+    string
+}
+
+/// An HTML `pre` element with a styled `span` for each run of text
+pub fn html(rows: &[(Vec<Block>, bool)]) -> String {
+    let mut string = "<pre style=\"font-family: monospace\">".to_string();
+    for (n, (row, wrapped)) in rows.iter().enumerate() {
+        for run in runs(row) {
+            let (fg, bg, bold) = style(&run[0]);
+            let mut css = Vec::new();
+            if let Some(fg) = fg {
+                css.push(format!("color: #{:06x}", fg));
+            }
+            if let Some(bg) = bg {
+                css.push(format!("background-color: #{:06x}", bg));
+            }
+            if bold {
+                css.push("font-weight: bold".to_string());
+            }
+            if !css.is_empty() {
+                string.push_str(&format!("<span style=\"{}\">", css.join("; ")));
+            }
+            for block in run {
+                match text(block) {
+                    '&' => string.push_str("&amp;"),
+                    '<' => string.push_str("&lt;"),
+                    '>' => string.push_str("&gt;"),
+                    '"' => string.push_str("&quot;"),
+                    c => string.push(c),
+                }
+            }
+            if !css.is_empty() {
+                string.push_str("</span>");
+            }
+        }
+        if !wrapped && n + 1 < rows.len() {
+            string.push('\n');
+        }
+    }
+    string.push_str("</pre>");
+    string
+}
+
+/// Text with SGR sequences setting the colors and weight of each run
+pub fn ansi(rows: &[(Vec<Block>, bool)]) -> String {
+    let mut string = String::new();
+    for (n, (row, wrapped)) in rows.iter().enumerate() {
+        for run in runs(row) {
+            let (fg, bg, bold) = style(&run[0]);
+            let mut sgr = "0".to_string();
+            if bold {
+                sgr.push_str(";1");
+            }
+            if let Some(fg) = fg {
+                sgr.push_str(&format!(
+                    ";38;2;{};{};{}",
+                    fg >> 16,
+                    (fg >> 8) & 0xFF,
+                    fg & 0xFF
+                ));
+            }
+            if let Some(bg) = bg {
+                sgr.push_str(&format!(
+                    ";48;2;{};{};{}",
+                    bg >> 16,
+                    (bg >> 8) & 0xFF,
+                    bg & 0xFF
+                ));
+            }
+            string.push_str(&format!("\x1B[{}m", sgr));
+            string.extend(run.iter().map(text));
+        }
+        if !wrapped && n + 1 < rows.len() {
+            // Reset before the line break, so the background does not fill the rest of the line
+            string.push_str("\x1B[0m\n");
+        }
+    }
+    string.push_str("\x1B[0m");
+    string
+}
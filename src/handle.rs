@@ -8,14 +8,18 @@ use console::Console;
 
 #[cfg(target_os = "redox")]
 pub fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
+    use std::fs::OpenOptions;
     use std::os::unix::io::AsRawFd;
+    use std::time::{Duration, Instant};
 
     use event::{EventFlags, EventQueue};
+    use syscall::data::TimeSpec;
 
     event::user_data! {
         enum EventSource {
             Window,
             Master,
+            Timer,
         }
     };
 
@@ -29,6 +33,18 @@ pub fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
     event_queue.subscribe(master_fd as usize, EventSource::Master, EventFlags::READ)
         .expect("terminal: failed to fevent master PTY");
 
+    // Wakes up the loop while a notification is shown or its command runs, so they are handled
+    // on time while the terminal is idle
+    let mut timer = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!("/scheme/time/{}", syscall::CLOCK_MONOTONIC))
+        .expect("terminal: failed to open timer");
+    event_queue.subscribe(timer.as_raw_fd() as usize, EventSource::Timer, EventFlags::READ)
+        .expect("terminal: failed to fevent timer");
+
+    let mut timer_armed = false;
+
     let mut handle_event = |event_source: EventSource| -> bool {
         match event_source {
             EventSource::Window => for event in console.window.events() {
@@ -75,6 +91,7 @@ pub fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
                     }
                 }
             }
+            EventSource::Timer => timer_armed = false,
         }
 
         if !console.input.is_empty() {
@@ -88,6 +105,25 @@ pub fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
             console.input.clear();
         }
 
+        console.tick();
+
+        if !timer_armed {
+            let delay = match console.toast {
+                Some((_, until)) => Some(until.saturating_duration_since(Instant::now())),
+                None if console.notifier.is_some() => Some(Duration::from_secs(1)),
+                None => None,
+            };
+            if let Some(delay) = delay {
+                let mut time = TimeSpec::default();
+                if timer.read(&mut time).is_ok() {
+                    let nsec = time.tv_nsec as u64 + delay.subsec_nanos() as u64;
+                    time.tv_sec += delay.as_secs() as i64 + (nsec / 1_000_000_000) as i64;
+                    time.tv_nsec = (nsec % 1_000_000_000) as i32;
+                    timer_armed = timer.write(&time).is_ok();
+                }
+            }
+        }
+
         true
     };
 
@@ -193,6 +229,8 @@ pub fn handle(console: &mut Console, master_fd: RawFd, process: &mut Child) {
             },
         }
 
+        console.tick();
+
         thread::sleep(Duration::new(0, 10));
     }
 
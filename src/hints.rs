@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+use config::{HintAction, HintRule};
+use regex::{Captures, Regex};
+
+/// A match that can be chosen by typing its label
+pub struct Hint {
+    /// Grid indices of the match
+    pub range: Range<usize>,
+    pub label: String,
+    pub action: HintAction,
+    /// Text passed to the action
+    pub text: String,
+}
+
+/// Hints shown on the screen, and the part of a label typed so far
+pub struct Hints {
+    pub hints: Vec<Hint>,
+    pub typed: String,
+}
+
+impl Hints {
+    /// Hints with labels starting with what has been typed
+    pub fn visible(&self) -> Vec<&Hint> {
+        self.hints
+            .iter()
+            .filter(|hint| hint.label.starts_with(&self.typed))
+            .collect()
+    }
+
+    /// The hint whose label has been typed completely
+    pub fn chosen(&self) -> Option<&Hint> {
+        self.hints.iter().find(|hint| hint.label == self.typed)
+    }
+}
+
+/// Labels of equal length made from the alphabet, so none is a prefix of another
+pub fn labels(alphabet: &[char], count: usize) -> Vec<String> {
+    if alphabet.len() < 2 {
+        return Vec::new();
+    }
+
+    let mut len = 1;
+    while alphabet.len().pow(len) < count {
+        len += 1;
+    }
+
+    (0..count)
+        .map(|mut n| {
+            let mut label = Vec::new();
+            for _ in 0..len {
+                label.push(alphabet[n % alphabet.len()]);
+                n /= alphabet.len();
+            }
+            label.into_iter().rev().collect()
+        })
+        .collect()
+}
+
+/// Replace `{0}`, `{1}` or `{name}` with the groups of a match. Other text in braces is kept.
+fn expand(template: &str, captures: &Captures) -> String {
+    let mut string = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        string.push_str(&rest[..open]);
+        rest = &rest[open..];
+
+        let group = rest.find('}').and_then(|close| {
+            let name = &rest[1..close];
+            let group = match name.parse::<usize>() {
+                Ok(i) => captures.get(i),
+                Err(_) => captures.name(name),
+            };
+            group.map(|group| (group.as_str(), close))
+        });
+        match group {
+            Some((value, close)) => {
+                string.push_str(value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                string.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    string.push_str(rest);
+    string
+}
+
+pub struct Rules {
+    rules: Vec<(Regex, HintRule)>,
+}
+
+impl Rules {
+    /// Compile the `[[hints.rules]]` of the config. Invalid rules are skipped and returned as
+    /// errors.
+    pub fn new(config: &[HintRule]) -> (Rules, Vec<String>) {
+        let mut rules = Vec::new();
+        let mut errors = Vec::new();
+        for rule in config.iter() {
+            match Regex::new(&rule.regex) {
+                Ok(regex) => rules.push((regex, rule.clone())),
+                Err(err) => errors.push(format!("invalid regex {:?}: {}", rule.regex, err)),
+            }
+        }
+        (Rules { rules }, errors)
+    }
+
+    /// Matches in a line of text, as character ranges with the action and its text. Matches that
+    /// overlap one found by an earlier rule are left out.
+    pub fn find(&self, chars: &[char]) -> Vec<(Range<usize>, HintAction, String)> {
+        let line: String = chars.iter().collect();
+        // Character index of each byte offset
+        let mut indices = vec![0; line.len() + 1];
+        for (i, (offset, c)) in line.char_indices().enumerate() {
+            for index in indices[offset..offset + c.len_utf8()].iter_mut() {
+                *index = i;
+            }
+        }
+        indices[line.len()] = chars.len();
+
+        let mut matches: Vec<(Range<usize>, HintAction, String)> = Vec::new();
+        for (regex, rule) in self.rules.iter() {
+            for captures in regex.captures_iter(&line) {
+                let whole = match captures.get(0) {
+                    Some(whole) if !whole.as_str().is_empty() => whole,
+                    _ => continue,
+                };
+                let range = indices[whole.start()]..indices[whole.end()];
+                let overlaps = matches
+                    .iter()
+                    .any(|(other, _, _)| range.start < other.end && other.start < range.end);
+                if !overlaps {
+                    matches.push((range, rule.action, expand(&rule.template, &captures)));
+                }
+            }
+        }
+        matches.sort_by_key(|(range, _, _)| range.start);
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn label_length() {
+        let alphabet: Vec<char> = "abc".chars().collect();
+        assert_eq!(labels(&alphabet, 3), ["a", "b", "c"]);
+        assert_eq!(labels(&alphabet, 4), ["aa", "ab", "ac", "ba"]);
+        assert!(labels(&alphabet[..1], 4).is_empty());
+    }
+
+    #[test]
+    fn templates() {
+        let regex = Regex::new(r"(?P<file>\w+\.rs):(\d+)").unwrap();
+        let captures = regex.captures("src/main.rs:42").unwrap();
+        assert_eq!(expand("{file} +{2}", &captures), "main.rs +42");
+        assert_eq!(expand("{0} {other}", &captures), "main.rs:42 {other}");
+    }
+}
@@ -0,0 +1,319 @@
+// Key encoding for the kitty keyboard protocol, see
+// https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+
+use orbclient;
+
+pub const DISAMBIGUATE: u32 = 1;
+pub const EVENT_TYPES: u32 = 2;
+pub const ALTERNATE_KEYS: u32 = 4;
+pub const ALL_KEYS: u32 = 8;
+pub const ASSOCIATED_TEXT: u32 = 16;
+
+pub const ALL: u32 = DISAMBIGUATE | EVENT_TYPES | ALTERNATE_KEYS | ALL_KEYS | ASSOCIATED_TEXT;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    fn any(&self) -> bool {
+        self.shift || self.alt || self.ctrl
+    }
+
+    fn value(&self) -> u32 {
+        1 + (self.shift as u32) + ((self.alt as u32) << 1) + ((self.ctrl as u32) << 2)
+    }
+}
+
+/// Characters produced by a key on a US layout, without and with shift
+fn text(scancode: u8) -> Option<(char, char)> {
+    Some(match scancode {
+        orbclient::K_A => ('a', 'A'),
+        orbclient::K_B => ('b', 'B'),
+        orbclient::K_C => ('c', 'C'),
+        orbclient::K_D => ('d', 'D'),
+        orbclient::K_E => ('e', 'E'),
+        orbclient::K_F => ('f', 'F'),
+        orbclient::K_G => ('g', 'G'),
+        orbclient::K_H => ('h', 'H'),
+        orbclient::K_I => ('i', 'I'),
+        orbclient::K_J => ('j', 'J'),
+        orbclient::K_K => ('k', 'K'),
+        orbclient::K_L => ('l', 'L'),
+        orbclient::K_M => ('m', 'M'),
+        orbclient::K_N => ('n', 'N'),
+        orbclient::K_O => ('o', 'O'),
+        orbclient::K_P => ('p', 'P'),
+        orbclient::K_Q => ('q', 'Q'),
+        orbclient::K_R => ('r', 'R'),
+        orbclient::K_S => ('s', 'S'),
+        orbclient::K_T => ('t', 'T'),
+        orbclient::K_U => ('u', 'U'),
+        orbclient::K_V => ('v', 'V'),
+        orbclient::K_W => ('w', 'W'),
+        orbclient::K_X => ('x', 'X'),
+        orbclient::K_Y => ('y', 'Y'),
+        orbclient::K_Z => ('z', 'Z'),
+        orbclient::K_0 => ('0', ')'),
+        orbclient::K_1 => ('1', '!'),
+        orbclient::K_2 => ('2', '@'),
+        orbclient::K_3 => ('3', '#'),
+        orbclient::K_4 => ('4', '$'),
+        orbclient::K_5 => ('5', '%'),
+        orbclient::K_6 => ('6', '^'),
+        orbclient::K_7 => ('7', '&'),
+        orbclient::K_8 => ('8', '*'),
+        orbclient::K_9 => ('9', '('),
+        orbclient::K_TICK => ('`', '~'),
+        orbclient::K_MINUS => ('-', '_'),
+        orbclient::K_EQUALS => ('=', '+'),
+        orbclient::K_BRACE_OPEN => ('[', '{'),
+        orbclient::K_BRACE_CLOSE => (']', '}'),
+        orbclient::K_BACKSLASH => ('\\', '|'),
+        orbclient::K_SEMICOLON => (';', ':'),
+        orbclient::K_QUOTE => ('\'', '"'),
+        orbclient::K_COMMA => (',', '<'),
+        orbclient::K_PERIOD => ('.', '>'),
+        orbclient::K_SLASH => ('/', '?'),
+        orbclient::K_SPACE => (' ', ' '),
+        _ => return None,
+    })
+}
+
+/// Number and final character of keys that do not produce text
+fn functional(scancode: u8) -> Option<(u32, char)> {
+    Some(match scancode {
+        orbclient::K_ESC => (27, 'u'),
+        orbclient::K_ENTER => (13, 'u'),
+        orbclient::K_TAB => (9, 'u'),
+        orbclient::K_BKSP => (127, 'u'),
+        orbclient::K_INS => (2, '~'),
+        orbclient::K_DEL => (3, '~'),
+        orbclient::K_LEFT => (1, 'D'),
+        orbclient::K_RIGHT => (1, 'C'),
+        orbclient::K_UP => (1, 'A'),
+        orbclient::K_DOWN => (1, 'B'),
+        orbclient::K_PGUP => (5, '~'),
+        orbclient::K_PGDN => (6, '~'),
+        orbclient::K_HOME => (1, 'H'),
+        orbclient::K_END => (1, 'F'),
+        orbclient::K_F1 => (1, 'P'),
+        orbclient::K_F2 => (1, 'Q'),
+        orbclient::K_F3 => (13, '~'),
+        orbclient::K_F4 => (1, 'S'),
+        orbclient::K_F5 => (15, '~'),
+        orbclient::K_F6 => (17, '~'),
+        orbclient::K_F7 => (18, '~'),
+        orbclient::K_F8 => (19, '~'),
+        orbclient::K_F9 => (20, '~'),
+        orbclient::K_F10 => (21, '~'),
+        orbclient::K_F11 => (23, '~'),
+        orbclient::K_F12 => (24, '~'),
+        orbclient::K_CAPS => (57358, 'u'),
+        orbclient::K_SCROLL => (57359, 'u'),
+        orbclient::K_NUM => (57360, 'u'),
+        orbclient::K_PRTSC => (57361, 'u'),
+        scancode @ orbclient::K_NUM_0..=orbclient::K_NUM_9 => {
+            (57399 + (scancode - orbclient::K_NUM_0) as u32, 'u')
+        }
+        orbclient::K_NUM_PERIOD => (57409, 'u'),
+        orbclient::K_NUM_SLASH => (57410, 'u'),
+        orbclient::K_NUM_ASTERISK => (57411, 'u'),
+        orbclient::K_NUM_MINUS => (57412, 'u'),
+        orbclient::K_NUM_PLUS => (57413, 'u'),
+        orbclient::K_NUM_ENTER => (57414, 'u'),
+        orbclient::K_LEFT_SHIFT => (57441, 'u'),
+        orbclient::K_LEFT_CTRL => (57442, 'u'),
+        orbclient::K_ALT => (57443, 'u'),
+        orbclient::K_LEFT_SUPER => (57444, 'u'),
+        orbclient::K_RIGHT_SHIFT => (57447, 'u'),
+        orbclient::K_RIGHT_CTRL => (57448, 'u'),
+        orbclient::K_RIGHT_SUPER => (57450, 'u'),
+        _ => return None,
+    })
+}
+
+/// Encode a key event according to the active protocol flags. Returns `None` when the legacy
+/// encoding should be used instead.
+pub fn encode(scancode: u8, pressed: bool, modifiers: Modifiers, flags: u32) -> Option<Vec<u8>> {
+    let all_keys = flags & ALL_KEYS == ALL_KEYS;
+    if flags == 0 || (!pressed && flags & EVENT_TYPES == 0) {
+        return None;
+    }
+
+    let (key, c, text) = if let Some((base, shifted)) = text(scancode) {
+        // Text is only ambiguous when combined with ctrl or alt
+        if !all_keys && !modifiers.ctrl && !modifiers.alt {
+            return None;
+        }
+
+        let key = if flags & ALTERNATE_KEYS == ALTERNATE_KEYS && modifiers.shift && shifted != base
+        {
+            format!("{}:{}", base as u32, shifted as u32)
+        } else {
+            format!("{}", base as u32)
+        };
+
+        let text = if all_keys
+            && flags & ASSOCIATED_TEXT == ASSOCIATED_TEXT
+            && pressed
+            && !modifiers.ctrl
+            && !modifiers.alt
+        {
+            Some(if modifiers.shift { shifted } else { base })
+        } else {
+            None
+        };
+
+        (key, 'u', text)
+    } else if let Some((number, c)) = functional(scancode) {
+        let legacy = match scancode {
+            // These stay usable in a shell after a crashed program left the protocol enabled
+            orbclient::K_ENTER | orbclient::K_TAB | orbclient::K_BKSP => {
+                !pressed || !modifiers.any()
+            }
+            orbclient::K_ESC | orbclient::K_NUM_0..=orbclient::K_NUM_ENTER => {
+                flags & DISAMBIGUATE == 0
+            }
+            _ if number >= 57358 => true,
+            _ => pressed && !modifiers.any(),
+        };
+        if legacy && !all_keys {
+            return None;
+        }
+
+        (format!("{}", number), c, None)
+    } else {
+        return None;
+    };
+
+    let mut params = key;
+    if modifiers.value() > 1 || !pressed || text.is_some() {
+        params.push_str(&format!(";{}", modifiers.value()));
+        if !pressed {
+            params.push_str(":3");
+        }
+    }
+    if let Some(text) = text {
+        params.push_str(&format!(";{}", text as u32));
+    }
+    // Legacy style keys leave out the number when it is one
+    if params == "1" {
+        params.clear();
+    }
+
+    Some(format!("\x1B[{}{}", params, c).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: false,
+    };
+    const CTRL: Modifiers = Modifiers {
+        shift: false,
+        alt: false,
+        ctrl: true,
+    };
+    const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        alt: false,
+        ctrl: false,
+    };
+    const CTRL_SHIFT: Modifiers = Modifiers {
+        shift: true,
+        alt: false,
+        ctrl: true,
+    };
+
+    fn encoded(scancode: u8, pressed: bool, modifiers: Modifiers, flags: u32) -> Option<String> {
+        encode(scancode, pressed, modifiers, flags).map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn disabled() {
+        assert_eq!(encoded(orbclient::K_A, true, CTRL, 0), None);
+        assert_eq!(encoded(orbclient::K_ESC, true, NONE, 0), None);
+    }
+
+    #[test]
+    fn disambiguate() {
+        assert_eq!(encoded(orbclient::K_A, true, NONE, DISAMBIGUATE), None);
+        assert_eq!(
+            encoded(orbclient::K_A, true, CTRL, DISAMBIGUATE),
+            Some("\x1B[97;5u".to_string())
+        );
+        assert_eq!(
+            encoded(orbclient::K_ESC, true, NONE, DISAMBIGUATE),
+            Some("\x1B[27u".to_string())
+        );
+        // Enter, tab and backspace stay legacy without modifiers
+        assert_eq!(encoded(orbclient::K_ENTER, true, NONE, DISAMBIGUATE), None);
+        assert_eq!(
+            encoded(orbclient::K_ENTER, true, CTRL, DISAMBIGUATE),
+            Some("\x1B[13;5u".to_string())
+        );
+        assert_eq!(encoded(orbclient::K_UP, true, NONE, DISAMBIGUATE), None);
+        assert_eq!(
+            encoded(orbclient::K_UP, true, SHIFT, DISAMBIGUATE),
+            Some("\x1B[1;2A".to_string())
+        );
+        assert_eq!(
+            encoded(orbclient::K_F5, true, CTRL, DISAMBIGUATE),
+            Some("\x1B[15;5~".to_string())
+        );
+    }
+
+    #[test]
+    fn event_types() {
+        assert_eq!(encoded(orbclient::K_A, false, CTRL, DISAMBIGUATE), None);
+        assert_eq!(
+            encoded(orbclient::K_A, false, CTRL, DISAMBIGUATE | EVENT_TYPES),
+            Some("\x1B[97;5:3u".to_string())
+        );
+        assert_eq!(
+            encoded(orbclient::K_F5, false, NONE, DISAMBIGUATE | EVENT_TYPES),
+            Some("\x1B[15;1:3~".to_string())
+        );
+    }
+
+    #[test]
+    fn alternate_keys() {
+        assert_eq!(
+            encoded(
+                orbclient::K_A,
+                true,
+                CTRL_SHIFT,
+                DISAMBIGUATE | ALTERNATE_KEYS
+            ),
+            Some("\x1B[97:65;6u".to_string())
+        );
+    }
+
+    #[test]
+    fn all_keys() {
+        assert_eq!(
+            encoded(orbclient::K_A, true, NONE, ALL_KEYS),
+            Some("\x1B[97u".to_string())
+        );
+        assert_eq!(
+            encoded(orbclient::K_A, true, SHIFT, ALL_KEYS | ASSOCIATED_TEXT),
+            Some("\x1B[97;2;65u".to_string())
+        );
+        assert_eq!(
+            encoded(orbclient::K_DOWN, true, NONE, ALL_KEYS),
+            Some("\x1B[B".to_string())
+        );
+        assert_eq!(
+            encoded(orbclient::K_LEFT_SHIFT, true, NONE, ALL_KEYS),
+            Some("\x1B[57441u".to_string())
+        );
+    }
+}
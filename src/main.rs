@@ -6,15 +6,20 @@ extern crate failure;
 extern crate libc;
 extern crate orbclient;
 extern crate orbfont;
+extern crate regex;
 extern crate toml;
+extern crate vte;
 extern crate xdg;
 
 #[cfg(target_os = "redox")]
 extern crate event;
+#[cfg(target_os = "redox")]
+extern crate syscall;
 
 use std::io::Write;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::process::CommandExt;
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::{cmp, env, io};
 
@@ -25,12 +30,23 @@ use getpty::getpty;
 use handle::handle;
 use slave_stdio::slave_stdio;
 
+mod base64;
 mod before_exec;
+mod bindings;
 mod config;
 mod console;
+mod copy_mode;
+mod export;
 mod getpty;
 mod handle;
+mod hints;
+mod keyboard;
+mod palette;
+mod parser;
+mod paste;
+mod selection;
 mod slave_stdio;
+mod url;
 
 fn main() {
     #[cfg(feature = "env_logger")]
@@ -89,12 +105,17 @@ fn main() {
             drop(slave_stdout);
             drop(slave_stdin);
 
+            let process_name = Path::new(&shell)
+                .file_name()
+                .map_or(shell.clone(), |name| name.to_string_lossy().into_owned());
             let mut console = Console::new(
                 &config,
                 columns * block_width as u32,
                 lines * block_height as u32,
                 block_width as usize,
                 block_height as usize,
+                master_fd,
+                &process_name,
             );
             handle(&mut console, master_fd, &mut process);
         }
@@ -0,0 +1,177 @@
+extern crate ransid;
+
+/// A color that applications can set, query and reset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// An entry of the 256 color palette, set with OSC 4
+    Palette(u8),
+    /// OSC 10
+    Foreground,
+    /// OSC 11
+    Background,
+    /// OSC 12
+    Cursor,
+}
+
+/// Colors that applications can change with OSC sequences, as `0xRRGGBB`
+pub struct Palette {
+    pub colors: [u32; 256],
+    /// Default foreground, used for ANSI color 7 as a foreground
+    pub foreground: u32,
+    /// Default background, used for ANSI color 0 as a background
+    pub background: u32,
+    /// Color of the cursor, it inverts the block under it if not set
+    pub cursor: Option<u32>,
+}
+
+impl Palette {
+    pub fn new() -> Palette {
+        let mut colors = [0; 256];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = Palette::default_color(i as u8);
+        }
+        Palette {
+            colors,
+            foreground: colors[7],
+            background: colors[0],
+            cursor: None,
+        }
+    }
+
+    pub fn default_color(i: u8) -> u32 {
+        ransid::Color::Ansi(i).as_rgb() & 0xFFFFFF
+    }
+
+    pub fn get(&self, target: Target) -> u32 {
+        match target {
+            Target::Palette(i) => self.colors[i as usize],
+            Target::Foreground => self.foreground,
+            Target::Background => self.background,
+            Target::Cursor => self.cursor.unwrap_or(self.foreground),
+        }
+    }
+
+    /// Set a color, or reset it to its default
+    pub fn set(&mut self, target: Target, rgb: Option<u32>) {
+        match target {
+            Target::Palette(i) => {
+                self.colors[i as usize] = rgb.unwrap_or_else(|| Palette::default_color(i))
+            }
+            Target::Foreground => {
+                self.foreground = rgb.unwrap_or_else(|| Palette::default_color(7))
+            }
+            Target::Background => {
+                self.background = rgb.unwrap_or_else(|| Palette::default_color(0))
+            }
+            Target::Cursor => self.cursor = rgb,
+        }
+    }
+
+    /// Resolve a foreground color. ransid does not tell the default foreground apart from color
+    /// 7, so both follow OSC 10.
+    pub fn fg(&self, color: ransid::Color) -> u32 {
+        match color {
+            ransid::Color::Ansi(7) => self.foreground,
+            ransid::Color::Ansi(i) => self.colors[i as usize],
+            color => color.as_rgb() & 0xFFFFFF,
+        }
+    }
+
+    /// Resolve a background color, color 0 follows OSC 11 like the default background
+    pub fn bg(&self, color: ransid::Color) -> u32 {
+        match color {
+            ransid::Color::Ansi(0) => self.background,
+            ransid::Color::Ansi(i) => self.colors[i as usize],
+            color => color.as_rgb() & 0xFFFFFF,
+        }
+    }
+}
+
+/// Parse an X11 color specification, `rgb:r/g/b` with one to four hex digits per component, or
+/// `#rgb` with one to four hex digits per component
+pub fn parse(spec: &str) -> Option<u32> {
+    // `rgb:` components are scaled, `#` components are the high bits of the color
+    let (components, scale): (Vec<&str>, bool) = if let Some(rest) = spec.strip_prefix("rgb:") {
+        (rest.split('/').collect(), true)
+    } else if let Some(rest) = spec.strip_prefix('#') {
+        let len = rest.len() / 3;
+        if len == 0 || rest.len() % 3 != 0 || !rest.is_ascii() {
+            return None;
+        }
+        (
+            vec![&rest[..len], &rest[len..len * 2], &rest[len * 2..]],
+            false,
+        )
+    } else {
+        return None;
+    };
+
+    if components.len() != 3 {
+        return None;
+    }
+    let mut rgb = 0;
+    for digits in components {
+        if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(digits, 16).ok()?;
+        let bits = digits.len() as u32 * 4;
+        let value = if scale {
+            let max = (1 << bits) - 1;
+            (value * 0xFF + max / 2) / max
+        } else {
+            (value << (16 - bits)) >> 8
+        };
+        rgb = rgb << 8 | value;
+    }
+    Some(rgb)
+}
+
+/// Format a color for a reply, as xterm does
+pub fn format(rgb: u32) -> String {
+    let r = (rgb >> 16) & 0xFF;
+    let g = (rgb >> 8) & 0xFF;
+    let b = rgb & 0xFF;
+    format!(
+        "rgb:{:02x}{:02x}/{:02x}{:02x}/{:02x}{:02x}",
+        r, r, g, g, b, b
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb() {
+        assert_eq!(parse("rgb:ff/00/80"), Some(0xFF0080));
+        assert_eq!(parse("rgb:f/0/8"), Some(0xFF0088));
+        assert_eq!(parse("rgb:ffff/0000/8080"), Some(0xFF0080));
+        assert_eq!(parse("rgb:fff/000/800"), Some(0xFF0080));
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(parse("#ff0080"), Some(0xFF0080));
+        assert_eq!(parse("#f08"), Some(0xF00080));
+        assert_eq!(parse("#fff000888"), Some(0xFF0088));
+        assert_eq!(parse("#ffff00008080"), Some(0xFF0080));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse("rgb:ff/00"), None);
+        assert_eq!(parse("rgb:ff/00/80/00"), None);
+        assert_eq!(parse("rgb:fffff/0/0"), None);
+        assert_eq!(parse("rgb:+f/0/0"), None);
+        assert_eq!(parse("#ff00"), None);
+        assert_eq!(parse("#"), None);
+        assert_eq!(parse("red"), None);
+    }
+
+    #[test]
+    fn reply() {
+        assert_eq!(format(0x12AB34), "rgb:1212/abab/3434");
+        assert_eq!(parse(&format(0x12AB34)), Some(0x12AB34));
+    }
+}
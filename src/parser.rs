@@ -0,0 +1,543 @@
+use std::{cmp, str};
+
+use base64;
+use keyboard;
+use palette::{self, Target};
+use url;
+
+/// Maximum depth of the kitty keyboard flag stack, older entries are dropped
+const KEYBOARD_STACK: usize = 16;
+
+/// Longest OSC sequence that is handled, enough for OSC 52 with a large clipboard, longer ones
+/// are dropped
+const MAX_OSC: usize = 4 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Event {
+    /// Data to send back to the PTY
+    Input { data: Vec<u8> },
+    /// The cursor may have been moved by something other than printing, so the next printed
+    /// character does not continue a wrapped row
+    CursorMove,
+    /// OSC 7, the shell changed its working directory
+    WorkingDirectory { host: String, path: String },
+    /// OSC 9;4, show or remove the progress of a task
+    Progress { progress: Option<Progress> },
+    /// OSC 9 or OSC 777;notify, show a notification
+    Notify { title: Option<String>, body: String },
+    /// OSC 8, start a hyperlink or end it if there is no URI
+    Hyperlink {
+        uri: Option<String>,
+        id: Option<String>,
+    },
+    /// OSC 52, set the clipboard, or the primary selection
+    SetClipboard { primary: bool, text: String },
+    /// OSC 52, report the contents of the clipboard, ending the reply like the query
+    QueryClipboard { primary: bool, bell: bool },
+    /// OSC 133;A, a prompt starts
+    PromptStart,
+    /// OSC 133;B, the command line starts
+    CommandStart,
+    /// OSC 133;C, the command was entered and its output starts
+    OutputStart,
+    /// OSC 133;D, the command finished
+    CommandEnd { exit: Option<i32> },
+    /// OSC 4, 10, 11 and 12 set a color, OSC 104 and 110 to 112 reset it
+    SetColor { target: Target, rgb: Option<u32> },
+    /// OSC 4, 10, 11 and 12 with `?` instead of a color, report it
+    QueryColor { target: Target },
+    /// OSC 0, 1 and 2, set the icon name, the window title or both
+    Title {
+        title: String,
+        icon: bool,
+        window: bool,
+    },
+    /// CSI 22 t, save the icon name, the window title or both
+    PushTitle { icon: bool, window: bool },
+    /// CSI 23 t, restore what was saved
+    PopTitle { icon: bool, window: bool },
+    /// CSI 20 t and CSI 21 t, report the icon name or the window title
+    ReportTitle { icon: bool },
+    /// A kitty keyboard protocol sequence, which ransid would treat as a cursor restore
+    KeyboardMode,
+}
+
+/// State of a task reported with OSC 9;4, with its percentage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Progress {
+    Normal(u8),
+    Error(u8),
+    Indeterminate,
+    Paused(u8),
+}
+
+/// Set or query a color, the specification is `?` for a query
+fn color<F: FnMut(Event)>(target: Target, spec: &[u8], callback: &mut F) {
+    if spec == b"?" {
+        callback(Event::QueryColor { target });
+    } else if let Some(rgb) = str::from_utf8(spec).ok().and_then(palette::parse) {
+        callback(Event::SetColor {
+            target,
+            rgb: Some(rgb),
+        });
+    }
+}
+
+/// Terminal state that ransid does not track, but that the input path depends on
+pub struct State {
+    /// DECCKM, cursor keys send SS3 sequences instead of CSI sequences
+    pub cursor_keys: bool,
+    /// DECKPAM, the numeric keypad sends application sequences
+    pub keypad: bool,
+    /// DECBKM, the backspace key sends BS instead of DEL
+    pub backarrow: bool,
+    /// State of DECBKM after a reset
+    pub backarrow_default: bool,
+    /// Pasted text is wrapped in bracketed paste markers
+    pub bracketed_paste: bool,
+    /// Active kitty keyboard protocol flags
+    pub keyboard: u32,
+    pub keyboard_stack: Vec<u32>,
+}
+
+impl State {
+    pub fn new() -> State {
+        State {
+            cursor_keys: false,
+            keypad: false,
+            backarrow: false,
+            backarrow_default: false,
+            bracketed_paste: false,
+            keyboard: 0,
+            keyboard_stack: Vec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        let backarrow_default = self.backarrow_default;
+        *self = State::new();
+        self.backarrow = backarrow_default;
+        self.backarrow_default = backarrow_default;
+    }
+
+    fn mode(&mut self, param: i64, value: bool) {
+        match param {
+            1 => self.cursor_keys = value,
+            67 => self.backarrow = value,
+            2004 => self.bracketed_paste = value,
+            _ => (),
+        }
+    }
+
+    fn keyboard<F: FnMut(Event)>(&mut self, params: &[i64], intermediate: char, callback: &mut F) {
+        let param = |i: usize, default: i64| params.get(i).copied().unwrap_or(default);
+        let flags = cmp::max(0, param(0, 0)) as u32 & keyboard::ALL;
+        match intermediate {
+            '?' => {
+                let report = format!("\x1B[?{}u", self.keyboard);
+                callback(Event::Input {
+                    data: report.into_bytes(),
+                });
+            }
+            '>' => {
+                if self.keyboard_stack.len() >= KEYBOARD_STACK {
+                    self.keyboard_stack.remove(0);
+                }
+                self.keyboard_stack.push(self.keyboard);
+                self.keyboard = flags;
+            }
+            '<' => {
+                for _ in 0..cmp::max(1, param(0, 1)) {
+                    match self.keyboard_stack.pop() {
+                        Some(keyboard) => self.keyboard = keyboard,
+                        None => {
+                            // Popping everything off the stack resets the flags
+                            self.keyboard = 0;
+                            break;
+                        }
+                    }
+                }
+            }
+            '=' => match param(1, 1) {
+                1 => self.keyboard = flags,
+                2 => self.keyboard |= flags,
+                3 => self.keyboard &= !flags,
+                _ => (),
+            },
+            _ => return,
+        }
+
+        callback(Event::KeyboardMode);
+    }
+
+    pub fn csi<F: FnMut(Event)>(
+        &mut self,
+        c: char,
+        params: &[i64],
+        intermediates: &[u8],
+        callback: &mut F,
+    ) {
+        match (intermediates.first().map(|b| *b as char), c) {
+            (Some('?'), 'h') => {
+                for param in params.iter() {
+                    self.mode(*param, true);
+                }
+            }
+            (Some('?'), 'l') => {
+                for param in params.iter() {
+                    self.mode(*param, false);
+                }
+            }
+            (Some('!'), 'p') => {
+                // Soft reset
+                self.cursor_keys = false;
+                self.keypad = false;
+            }
+            (Some(intermediate), 'u') => {
+                self.keyboard(params, intermediate, callback);
+            }
+            (None, 't') => {
+                // The second parameter picks the icon name, the window title or both
+                let icon = params.get(1) != Some(&2);
+                let window = params.get(1) != Some(&1);
+                match params.first() {
+                    Some(20) => callback(Event::ReportTitle { icon: true }),
+                    Some(21) => callback(Event::ReportTitle { icon: false }),
+                    Some(22) => callback(Event::PushTitle { icon, window }),
+                    Some(23) => callback(Event::PopTitle { icon, window }),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn osc<F: FnMut(Event)>(&mut self, params: &[&[u8]], bell: bool, callback: &mut F) {
+        let param = |i: usize| params.get(i).and_then(|param| str::from_utf8(param).ok());
+        match param(0) {
+            Some(code @ "0") | Some(code @ "1") | Some(code @ "2") => {
+                // The title can contain semicolons
+                let title = params
+                    .get(1..)
+                    .and_then(|params| String::from_utf8(params.join(&b';')).ok());
+                if let Some(title) = title {
+                    callback(Event::Title {
+                        title,
+                        icon: code != "2",
+                        window: code != "1",
+                    });
+                }
+            }
+            Some("7") => {
+                // A file URL, the host can be empty
+                let uri = params
+                    .get(1..)
+                    .and_then(|params| String::from_utf8(params.join(&b';')).ok());
+                let rest = match uri {
+                    Some(ref uri) if uri.starts_with("file://") => &uri[7..],
+                    _ => return,
+                };
+                let (host, path) = match rest.find('/') {
+                    Some(i) => rest.split_at(i),
+                    None => return,
+                };
+                if let Ok(path) = String::from_utf8(url::percent_decode(path)) {
+                    callback(Event::WorkingDirectory {
+                        host: host.to_string(),
+                        path,
+                    });
+                }
+            }
+            Some("8") => {
+                let id = param(1).and_then(|param| {
+                    param
+                        .split(':')
+                        .find(|pair| pair.starts_with("id="))
+                        .map(|pair| pair[3..].to_string())
+                });
+                // The URI can contain semicolons
+                let uri = params
+                    .get(2..)
+                    .and_then(|params| String::from_utf8(params.join(&b';')).ok())
+                    .filter(|uri| !uri.is_empty());
+                callback(Event::Hyperlink { uri, id });
+            }
+            // ConEmu progress, which needs a state to tell it apart from a notification saying 4
+            Some("9") if param(1) == Some("4") && params.len() > 2 => {
+                let percent = param(3)
+                    .and_then(|percent| percent.parse::<u8>().ok())
+                    .map_or(0, |percent| cmp::min(percent, 100));
+                let progress = match param(2) {
+                    Some("1") => Some(Progress::Normal(percent)),
+                    Some("2") => Some(Progress::Error(percent)),
+                    Some("3") => Some(Progress::Indeterminate),
+                    Some("4") => Some(Progress::Paused(percent)),
+                    _ => None,
+                };
+                callback(Event::Progress { progress });
+            }
+            // Other ConEmu commands, such as 9;9 reporting the working directory at every prompt
+            Some("9")
+                if params.len() > 2
+                    && !params[1].is_empty()
+                    && params[1].iter().all(u8::is_ascii_digit) => {}
+            Some("9") => {
+                // The text can contain semicolons
+                let body = params
+                    .get(1..)
+                    .and_then(|params| String::from_utf8(params.join(&b';')).ok());
+                if let Some(body) = body {
+                    callback(Event::Notify { title: None, body });
+                }
+            }
+            Some("777") if param(1) == Some("notify") => {
+                let title = param(2).map(|title| title.to_string());
+                let body = params
+                    .get(3..)
+                    .and_then(|params| String::from_utf8(params.join(&b';')).ok())
+                    .unwrap_or_default();
+                callback(Event::Notify { title, body });
+            }
+            Some("52") => {
+                // Only the first target is used, an empty list means the clipboard
+                let primary = matches!(
+                    param(1).and_then(|targets| targets.chars().next()),
+                    Some('p') | Some('s')
+                );
+                match params.get(2) {
+                    Some(&b"?") => callback(Event::QueryClipboard { primary, bell }),
+                    Some(data) => {
+                        let text =
+                            base64::decode(data).and_then(|data| String::from_utf8(data).ok());
+                        if let Some(text) = text {
+                            callback(Event::SetClipboard { primary, text });
+                        }
+                    }
+                    None => (),
+                }
+            }
+            Some("4") => {
+                for pair in params[1..].chunks(2) {
+                    let index = str::from_utf8(pair[0])
+                        .ok()
+                        .and_then(|index| index.parse::<u8>().ok());
+                    if let (Some(index), Some(spec)) = (index, pair.get(1)) {
+                        color(Target::Palette(index), spec, callback);
+                    }
+                }
+            }
+            Some(code @ "10") | Some(code @ "11") | Some(code @ "12") => {
+                // Further parameters go on to the next colors, so OSC 10 can set all three
+                let targets = [Target::Foreground, Target::Background, Target::Cursor];
+                let first = match code {
+                    "10" => 0,
+                    "11" => 1,
+                    _ => 2,
+                };
+                for (target, spec) in targets[first..].iter().zip(&params[1..]) {
+                    color(*target, spec, callback);
+                }
+            }
+            Some("104") => {
+                // Without indices the whole palette is reset
+                let targets: Vec<Target> = if params[1..].iter().all(|param| param.is_empty()) {
+                    (0..=255).map(Target::Palette).collect()
+                } else {
+                    params[1..]
+                        .iter()
+                        .filter_map(|param| str::from_utf8(param).ok()?.parse().ok())
+                        .map(Target::Palette)
+                        .collect()
+                };
+                for target in targets {
+                    callback(Event::SetColor { target, rgb: None });
+                }
+            }
+            Some("133") => match param(1) {
+                Some("A") => callback(Event::PromptStart),
+                Some("B") => callback(Event::CommandStart),
+                Some("C") => callback(Event::OutputStart),
+                Some("D") => callback(Event::CommandEnd {
+                    exit: param(2).and_then(|exit| exit.parse().ok()),
+                }),
+                _ => (),
+            },
+            Some("110") => callback(Event::SetColor {
+                target: Target::Foreground,
+                rgb: None,
+            }),
+            Some("111") => callback(Event::SetColor {
+                target: Target::Background,
+                rgb: None,
+            }),
+            Some("112") => callback(Event::SetColor {
+                target: Target::Cursor,
+                rgb: None,
+            }),
+            _ => (),
+        }
+    }
+
+    pub fn esc(&mut self, c: char, intermediates: &[u8]) {
+        if !intermediates.is_empty() {
+            return;
+        }
+
+        match c {
+            '=' => self.keypad = true,
+            '>' => self.keypad = false,
+            'c' => self.reset(),
+            _ => (),
+        }
+    }
+}
+
+struct Performer<'a, F: FnMut(usize, Event) + 'a> {
+    state: &'a mut State,
+    offset: usize,
+    callback: &'a mut F,
+    /// The OSC sequence ending with this byte was too long
+    drop_osc: bool,
+}
+
+impl<'a, F: FnMut(usize, Event)> vte::Perform for Performer<'a, F> {
+    fn print(&mut self, _c: char) {}
+
+    fn execute(&mut self, _byte: u8) {
+        (self.callback)(self.offset, Event::CursorMove);
+    }
+
+    fn hook(&mut self, _params: &[i64], _intermediates: &[u8], _ignore: bool, _action: char) {}
+
+    fn put(&mut self, _byte: u8) {}
+
+    fn unhook(&mut self) {}
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        if self.drop_osc {
+            return;
+        }
+        let offset = self.offset;
+        let callback = &mut self.callback;
+        self.state.osc(params, bell_terminated, &mut |event| {
+            callback(offset, event)
+        });
+    }
+
+    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], _ignore: bool, c: char) {
+        let offset = self.offset;
+        let callback = &mut self.callback;
+        self.state.csi(c, params, intermediates, &mut |event| {
+            callback(offset, event)
+        });
+        if c != 'm' {
+            callback(offset, Event::CursorMove);
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        self.state.esc(byte as char, intermediates);
+        // The string terminator ends OSC sequences without moving the cursor
+        if byte != b'\\' {
+            (self.callback)(self.offset, Event::CursorMove);
+        }
+    }
+}
+
+/// Parses the output of the PTY in parallel to ransid, handling the sequences it ignores
+pub struct Parser {
+    pub parser: vte::Parser,
+    pub state: State,
+    /// Length of the OSC sequence being received
+    osc_len: Option<usize>,
+    /// The last byte was an escape, which starts an OSC sequence if `]` follows
+    escape: bool,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            parser: vte::Parser::new(),
+            state: State::new(),
+            osc_len: None,
+            escape: false,
+        }
+    }
+
+    /// Events are passed along with the offset of the byte that completed their sequence
+    pub fn write<F: FnMut(usize, Event)>(&mut self, bytes: &[u8], mut callback: F) {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            // vte buffers OSC sequences without a limit, so the rest of a long one is not passed
+            // on and the sequence is dropped when it ends
+            let mut drop_osc = false;
+            match self.osc_len {
+                Some(len) if matches!(byte, 0x07 | 0x18 | 0x1A | 0x1B) => {
+                    self.osc_len = None;
+                    drop_osc = len > MAX_OSC;
+                }
+                Some(len) => {
+                    self.osc_len = Some(len.saturating_add(1));
+                    if len >= MAX_OSC {
+                        continue;
+                    }
+                }
+                None if self.escape && byte == b']' => self.osc_len = Some(0),
+                None => (),
+            }
+            self.escape = byte == 0x1B;
+
+            self.parser.advance(
+                &mut Performer {
+                    state: &mut self.state,
+                    offset,
+                    callback: &mut callback,
+                    drop_osc,
+                },
+                byte,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(bytes: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        Parser::new().write(bytes, |_offset, event| events.push(event));
+        events
+    }
+
+    #[test]
+    fn clipboard_query_ends_like_the_request() {
+        match events(b"\x1B]52;c;?\x07").as_slice() {
+            [Event::QueryClipboard {
+                primary: false,
+                bell: true,
+            }] => (),
+            events => panic!("{:?}", events),
+        }
+        match events(b"\x1B]52;p;?\x1B\\").as_slice() {
+            [Event::QueryClipboard {
+                primary: true,
+                bell: false,
+            }] => (),
+            events => panic!("{:?}", events),
+        }
+    }
+
+    #[test]
+    fn long_osc_is_dropped() {
+        let mut bytes = b"\x1B]52;c;".to_vec();
+        bytes.resize(MAX_OSC + 100, b'A');
+        bytes.extend(b"\x07\x1B]52;c;YQ==\x07");
+        match events(&bytes).as_slice() {
+            [Event::SetClipboard {
+                primary: false,
+                text,
+            }] => assert_eq!(text, "a"),
+            events => panic!("{:?}", events),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+/// Control characters other than tabs and line breaks, which a shell or the terminal would act on
+fn is_dangerous(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => false,
+        c => c.is_control(),
+    }
+}
+
+/// A paste waiting for the user to confirm it
+pub struct PendingPaste {
+    pub text: String,
+    pub strip: bool,
+    pub join: bool,
+    line_breaks: usize,
+    dangerous: bool,
+}
+
+impl PendingPaste {
+    /// Returns `None` if the text is safe to paste without asking
+    pub fn new(text: &str) -> Option<PendingPaste> {
+        let line_breaks = text
+            .replace("\r\n", "\n")
+            .chars()
+            .filter(|&c| c == '\n' || c == '\r')
+            .count();
+        let dangerous = text.chars().any(is_dangerous);
+
+        if line_breaks == 0 && !dangerous {
+            return None;
+        }
+
+        Some(PendingPaste {
+            text: text.to_string(),
+            strip: dangerous,
+            join: false,
+            line_breaks,
+            dangerous,
+        })
+    }
+
+    /// The text to paste, with the chosen conversions applied
+    pub fn converted(&self) -> String {
+        let mut text = self.text.clone();
+        if self.strip {
+            text = text.chars().filter(|&c| !is_dangerous(c)).collect();
+        }
+        if self.join {
+            text = text.replace("\r\n", " ").replace(['\n', '\r'], " ");
+        }
+        text
+    }
+
+    /// Text of the confirmation overlay
+    pub fn prompt(&self) -> Vec<String> {
+        let contents = match (self.line_breaks, self.dangerous) {
+            (0, _) => "control characters".to_string(),
+            (1, false) => "a line break".to_string(),
+            (n, false) => format!("{} line breaks", n),
+            (1, true) => "a line break and control characters".to_string(),
+            (n, true) => format!("{} line breaks and control characters", n),
+        };
+        let check = |value: bool| if value { "[x]" } else { "[ ]" };
+
+        vec![
+            format!("The pasted text contains {}", contents),
+            String::new(),
+            format!("S      {} Strip control characters", check(self.strip)),
+            format!("N      {} Convert line breaks to spaces", check(self.join)),
+            String::new(),
+            "Enter  Paste".to_string(),
+            "Esc    Cancel".to_string(),
+        ]
+    }
+}
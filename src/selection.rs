@@ -0,0 +1,89 @@
+use std::cmp;
+use std::ops::Range;
+
+/// A cell position that stays with the text when it scrolls
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Point {
+    /// Line counted from the top of the screen before any scrolling, negative lines have been
+    /// scrolled in from above
+    pub line: isize,
+    pub column: usize,
+}
+
+/// A selected part of the grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection {
+    /// Position where the selection was started
+    pub start: Point,
+    /// Position the selection was extended to
+    pub end: Point,
+    /// Select the rectangle with start and end as corners, both included, instead of the text
+    /// between them
+    pub block: bool,
+}
+
+impl Selection {
+    pub fn new(start: Point, end: Point) -> Selection {
+        Selection {
+            start,
+            end,
+            block: false,
+        }
+    }
+
+    /// Ranges of selected grid indices, one per visible row that has selected cells. `top` is the
+    /// line shown in the first row of the grid.
+    pub fn ranges(&self, top: isize, w: usize, h: usize) -> Vec<Range<usize>> {
+        let start = cmp::min(self.start, self.end);
+        let end = cmp::max(self.start, self.end);
+        let left = cmp::min(self.start.column, self.end.column);
+        let right = cmp::max(self.start.column, self.end.column);
+        (start.line..=end.line)
+            .filter_map(|line| {
+                let y = line - top;
+                if y < 0 || y >= h as isize {
+                    return None;
+                }
+                let y = y as usize;
+
+                let (from, to) = if self.block {
+                    (left, right + 1)
+                } else {
+                    (
+                        if line == start.line { start.column } else { 0 },
+                        if line == end.line { end.column } else { w },
+                    )
+                };
+                Some(y * w + cmp::min(from, w)..y * w + cmp::min(to, w))
+            })
+            .filter(|range| !range.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(line: isize, column: usize) -> Point {
+        Point { line, column }
+    }
+
+    #[test]
+    fn text_ranges() {
+        let selection = Selection::new(point(1, 5), point(0, 2));
+        assert_eq!(selection.ranges(0, 10, 3), [2..10, 10..15]);
+        // Lines scrolled off the screen are left out
+        assert_eq!(selection.ranges(1, 10, 3), vec![0..5]);
+    }
+
+    #[test]
+    fn block_ranges() {
+        let selection = Selection {
+            start: point(0, 4),
+            end: point(2, 4),
+            block: true,
+        };
+        assert_eq!(selection.ranges(0, 10, 3), [4..5, 14..15, 24..25]);
+    }
+}
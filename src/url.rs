@@ -0,0 +1,169 @@
+use std::ops::Range;
+use std::str;
+
+const SCHEMES: &[&str] = &[
+    "http://", "https://", "ftp://", "file://", "git://", "ssh://", "mailto:",
+];
+
+fn is_url_char(c: char) -> bool {
+    match c {
+        '\0' | '<' | '>' | '"' | '\'' | '`' | '{' | '}' | '|' | '\\' | '^' => false,
+        c => !c.is_whitespace() && !c.is_control(),
+    }
+}
+
+/// Ranges of the URLs in a line of text
+pub fn find(chars: &[char]) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let boundary = i == 0 || !chars[i - 1].is_alphanumeric();
+        let scheme = SCHEMES.iter().find(|scheme| {
+            scheme
+                .chars()
+                .enumerate()
+                .all(|(j, c)| chars.get(i + j).map(|c| c.to_ascii_lowercase()) == Some(c))
+        });
+        let scheme_len = match scheme {
+            Some(scheme) if boundary => scheme.len(),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let mut end = i + scheme_len;
+        while end < chars.len() && is_url_char(chars[end]) {
+            end += 1;
+        }
+
+        // Leave out punctuation that ends the surrounding sentence, and closing brackets that were
+        // not opened in the URL
+        while end > i + scheme_len {
+            let c = chars[end - 1];
+            let open = match c {
+                ')' => Some('('),
+                ']' => Some('['),
+                _ => None,
+            };
+            let unbalanced = match open {
+                Some(open) => {
+                    let opened = chars[i..end].iter().filter(|&&c2| c2 == open).count();
+                    let closed = chars[i..end].iter().filter(|&&c2| c2 == c).count();
+                    closed > opened
+                }
+                None => false,
+            };
+            if unbalanced || ".,:;!?".contains(c) {
+                end -= 1;
+            } else {
+                break;
+            }
+        }
+
+        if end > i + scheme_len {
+            urls.push(i..end);
+        }
+        i = end;
+    }
+    urls
+}
+
+/// Decode the `%XX` escapes of a URL path, invalid escapes are kept as they are
+pub fn percent_decode(string: &str) -> Vec<u8> {
+    let bytes = string.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Whether a link starts with a scheme like `https:`, so a program it is passed to cannot take it
+/// for an option
+pub fn has_scheme(link: &str) -> bool {
+    let scheme = match link.find(':') {
+        Some(end) => &link[..end],
+        None => return false,
+    };
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(text: &str) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        find(&chars)
+            .into_iter()
+            .map(|range| chars[range].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn trailing_punctuation() {
+        assert_eq!(
+            urls("see https://example.com/a."),
+            ["https://example.com/a"]
+        );
+        assert_eq!(
+            urls("https://example.com/?a=1, or"),
+            ["https://example.com/?a=1"]
+        );
+        assert_eq!(urls("\"https://example.com\""), ["https://example.com"]);
+    }
+
+    #[test]
+    fn brackets() {
+        assert_eq!(urls("(https://example.com/a)"), ["https://example.com/a"]);
+        assert_eq!(
+            urls("(https://en.wikipedia.org/wiki/Rust_(language))"),
+            ["https://en.wikipedia.org/wiki/Rust_(language)"]
+        );
+        assert_eq!(urls("[https://example.com/a]."), ["https://example.com/a"]);
+    }
+
+    #[test]
+    fn boundaries() {
+        assert!(urls("xhttps://example.com").is_empty());
+        assert!(urls("https://").is_empty());
+        assert_eq!(
+            urls("HTTPS://example.com ftp://example.org"),
+            ["HTTPS://example.com", "ftp://example.org"]
+        );
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(percent_decode("/a%20b%2Fc"), b"/a b/c");
+        assert_eq!(percent_decode("%+1%4%zz%"), b"%+1%4%zz%");
+    }
+
+    #[test]
+    fn scheme() {
+        assert!(has_scheme("https://example.com"));
+        assert!(has_scheme("mailto:someone@example.com"));
+        assert!(!has_scheme("--help"));
+        assert!(!has_scheme("-x:y"));
+        assert!(!has_scheme("/tmp/file"));
+    }
+}